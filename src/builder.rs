@@ -0,0 +1,226 @@
+use std::time::Duration;
+
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    error, types::LoginResponse, ChallengeKind, Client, Credentials, Error, Login, LoginChallenge,
+    Region, Result,
+};
+
+/// A builder for [`Client`], for customizing the endpoints and HTTP client it uses.
+#[derive(Debug)]
+pub struct ClientBuilder {
+    region: Region,
+    api_url: Option<Url>,
+    web_url: Option<Url>,
+    timeout: Option<Duration>,
+    mqtt_host: Option<String>,
+    mqtt_port: Option<u16>,
+    mqtt_tls: bool,
+    user_agent: Option<String>,
+    http: Option<reqwest::Client>,
+    connect_timeout: Option<Duration>,
+}
+
+/// The resolved set of URLs a [`Client`] talks to.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    api_url: String,
+    web_url: String,
+    pub(crate) mqtt_host: String,
+    pub(crate) mqtt_port: u16,
+    pub(crate) mqtt_tls: bool,
+}
+
+impl Endpoints {
+    /// Get the URL for the given path on the REST API.
+    pub(crate) fn api(&self, path: &str) -> String {
+        format!("{}/{path}", self.api_url)
+    }
+
+    /// Get the URL for the given path on the website API, used for some login flows.
+    pub(crate) fn web(&self, path: &str) -> String {
+        format!("{}/{path}", self.web_url)
+    }
+}
+
+impl ClientBuilder {
+    /// Create a new builder using the default endpoints for the given region.
+    #[must_use]
+    pub const fn new(region: Region) -> Self {
+        Self {
+            region,
+            http: None,
+            api_url: None,
+            web_url: None,
+            timeout: None,
+            mqtt_host: None,
+            mqtt_port: None,
+            mqtt_tls: true,
+            user_agent: None,
+            connect_timeout: None,
+        }
+    }
+
+    /// Override the base URL of the REST API (e.g. `https://api.bambulab.com`).
+    #[must_use]
+    pub fn api_url(mut self, url: Url) -> Self {
+        self.api_url = Some(url);
+        self
+    }
+
+    /// Override the base URL of the website API used for two-factor login (e.g. `https://bambulab.com`).
+    #[must_use]
+    pub fn web_url(mut self, url: Url) -> Self {
+        self.web_url = Some(url);
+        self
+    }
+
+    /// Override the hostname of the MQTT broker.
+    #[must_use]
+    pub fn mqtt_host(mut self, host: impl Into<String>) -> Self {
+        self.mqtt_host = Some(host.into());
+        self
+    }
+
+    /// Override the port of the MQTT broker. Defaults to `8883`.
+    #[must_use]
+    pub const fn mqtt_port(mut self, port: u16) -> Self {
+        self.mqtt_port = Some(port);
+        self
+    }
+
+    /// Set whether to connect to the MQTT broker over TLS, verifying its certificate. Enabled by default.
+    ///
+    /// Disabling this allows connecting to a local broker without a certificate, e.g. a mock broker in tests.
+    #[must_use]
+    pub const fn mqtt_tls(mut self, enabled: bool) -> Self {
+        self.mqtt_tls = enabled;
+        self
+    }
+
+    /// Use a preconfigured HTTP client. When set, the user agent and timeouts configured on this builder are ignored.
+    #[must_use]
+    pub fn http_client(mut self, client: reqwest::Client) -> Self {
+        self.http = Some(client);
+        self
+    }
+
+    /// Set the `User-Agent` header sent with every request.
+    #[must_use]
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Set a timeout for each request, from connecting until the response body has been read.
+    #[must_use]
+    pub const fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set a timeout for establishing a connection.
+    #[must_use]
+    pub const fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Log in with the provided credentials. See [`Client::login`].
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the HTTP client cannot be built, the login request fails or the response cannot be decoded.
+    pub async fn login(self, email: &str, password: &str) -> Result<Login> {
+        let region = self.region;
+        let (http, endpoints) = self.build()?;
+
+        let response = http
+            .post(endpoints.api("v1/user-service/user/login"))
+            .json(&json!({ "account": email, "password": password }))
+            .send()
+            .await?;
+        let response = error::json::<LoginResponse>(response).await?;
+
+        let kind = match response.login_type.as_deref() {
+            None | Some("") => {
+                return Ok(Login::Success(Client::with_credentials(
+                    region,
+                    http,
+                    endpoints,
+                    response.into(),
+                )?))
+            }
+            Some("verifyCode") => ChallengeKind::VerifyCode,
+            Some("tfa") => ChallengeKind::TwoFactor,
+            Some(other) => return Err(Error::UnsupportedLoginType(other.to_string())),
+        };
+
+        Ok(Login::Challenge(LoginChallenge {
+            kind,
+            http,
+            region,
+            endpoints,
+            email: email.to_string(),
+            tfa_key: response.tfa_key,
+        }))
+    }
+
+    /// Create a client from a previously issued access token. See [`Client::from_token`].
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the HTTP client cannot be built, or the token is empty or cannot be decoded.
+    pub fn from_token(self, access_token: impl Into<String>) -> Result<Client> {
+        self.from_credentials(Credentials::from_access_token(access_token))
+    }
+
+    /// Create a client from persisted credentials. See [`Client::from_credentials`].
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the HTTP client cannot be built, or the access token is empty or cannot be decoded.
+    pub fn from_credentials(self, credentials: Credentials) -> Result<Client> {
+        let region = self.region;
+        let (http, endpoints) = self.build()?;
+
+        Client::with_credentials(region, http, endpoints, credentials)
+    }
+
+    fn build(self) -> Result<(reqwest::Client, Endpoints)> {
+        let endpoints = Endpoints {
+            api_url: self.api_url.map_or_else(
+                || self.region.api_url().to_string(),
+                |url| url.as_str().trim_end_matches('/').to_string(),
+            ),
+            web_url: self.web_url.map_or_else(
+                || self.region.web_url().to_string(),
+                |url| url.as_str().trim_end_matches('/').to_string(),
+            ),
+            mqtt_host: self
+                .mqtt_host
+                .unwrap_or_else(|| self.region.mqtt_host().to_string()),
+            mqtt_port: self.mqtt_port.unwrap_or(8883),
+            mqtt_tls: self.mqtt_tls,
+        };
+
+        if let Some(http) = self.http {
+            return Ok((http, endpoints));
+        }
+
+        let mut builder = reqwest::Client::builder();
+        if let Some(user_agent) = self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+
+        Ok((builder.build()?, endpoints))
+    }
+}
@@ -0,0 +1,250 @@
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::{
+    error,
+    types::{CreateTaskResponse, ProfileResponse, Project, UploadNotification},
+    Client, CloudPrint, Device, Error, PrintJob, Result, Task,
+};
+
+/// How many times to check whether an upload has been processed, and how long to wait between checks.
+const UPLOAD_POLL_ATTEMPTS: usize = 30;
+const UPLOAD_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+impl Client {
+    /// Create a cloud project to upload a 3MF file to.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the request fails or the API rejects it.
+    pub async fn create_project(&self, name: &str) -> Result<Project> {
+        let response = self
+            .send(|http| {
+                http.post(self.endpoints.api("v1/iot-service/api/user/project"))
+                    .json(&json!({ "name": name }))
+            })
+            .await?;
+
+        error::json(response).await
+    }
+
+    /// Upload the contents of a 3MF file to a project's signed storage URL, and wait for the cloud to process it.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the upload fails, the storage rejects it, or the cloud fails to process it in time.
+    pub async fn upload_project(
+        &self,
+        project: &Project,
+        file: impl Into<reqwest::Body>,
+    ) -> Result<()> {
+        // The URL is already signed, so sending the access token along would only leak it to the storage provider.
+        let response = self
+            .http
+            .put(project.upload_url.clone())
+            .body(file)
+            .send()
+            .await?;
+
+        error::error_for_status(response).await?;
+
+        self.wait_for_upload(project).await
+    }
+
+    /// Poll the upload ticket until the cloud has finished processing the file.
+    async fn wait_for_upload(&self, project: &Project) -> Result<()> {
+        for _ in 0..UPLOAD_POLL_ATTEMPTS {
+            let response = self
+                .send(|http| {
+                    http.get(self.endpoints.api("v1/iot-service/api/user/notification"))
+                        .query(&[("action", "upload"), ("ticket", &project.upload_ticket)])
+                })
+                .await?;
+            let notification = error::json::<UploadNotification>(response).await?;
+
+            match notification.status.as_str() {
+                "" | "pending" | "running" | "uploading" => {
+                    tokio::time::sleep(UPLOAD_POLL_INTERVAL).await;
+                }
+                "success" | "succeeded" | "completed" | "done" => return Ok(()),
+                "failed" | "fail" | "error" => {
+                    return Err(Error::UploadFailed(notification.message));
+                }
+                status => {
+                    return Err(Error::UploadFailed(format!(
+                        "unexpected upload status `{status}`"
+                    )));
+                }
+            }
+        }
+
+        Err(Error::UploadTimeout)
+    }
+
+    /// Record a print of an uploaded project on the given device, returning the ID of the created [`Task`].
+    ///
+    /// This only adds the print to the account's history, use [`Client::submit_print`] to also start it.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the request fails or the API rejects it.
+    pub async fn create_task(
+        &self,
+        device_id: &str,
+        project: &Project,
+        job: &PrintJob,
+    ) -> Result<u64> {
+        self.create_task_for(device_id, &project.model_id, &project.profile_id, job)
+            .await
+    }
+
+    async fn create_task_for(
+        &self,
+        device_id: &str,
+        model_id: &str,
+        profile_id: &str,
+        job: &PrintJob,
+    ) -> Result<u64> {
+        let ams_mapping = job
+            .ams_mapping_ids()
+            .into_iter()
+            .map(|ams| json!({ "ams": ams }))
+            .collect::<Vec<_>>();
+
+        let response = self
+            .send(|http| {
+                http.post(self.endpoints.api("v1/user-service/my/task"))
+                    .json(&json!({
+                        "deviceId": device_id,
+                        "modelId": model_id,
+                        "profileId": profile_id,
+                        "plateIndex": job.plate,
+                        "title": job.name,
+                        "bedType": job.bed_type,
+                        "amsDetailMapping": ams_mapping,
+                        "mode": "cloud_file",
+                        "isPublicProfile": false,
+                    }))
+            })
+            .await?;
+
+        Ok(error::json::<CreateTaskResponse>(response).await?.id)
+    }
+
+    /// Upload a 3MF file to the cloud and start printing it on the given device, without needing access to its network.
+    ///
+    /// The job's file name is used as the project name, and its plate, AMS mapping and options are sent to the printer.
+    ///
+    /// The print is recorded in the account's history before the device is told to start it, so if the device cannot be
+    /// reached, is already printing or rejects the print, the history will contain a task that never printed.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if any of the API requests fail, or the printer cannot be reached over MQTT or rejects the print.
+    pub async fn submit_print(
+        &self,
+        device: &Device,
+        file: impl Into<reqwest::Body>,
+        job: &PrintJob,
+    ) -> Result<CloudPrint> {
+        let project = self.create_project(&job.name).await?;
+        self.upload_project(&project, file).await?;
+
+        // The upload URL only accepts uploads, so the printer needs a separately signed download URL.
+        let profile = self
+            .get_profile_file(&project.model_id, &project.profile_id)
+            .await?;
+        let task_id = self.create_task(&device.dev_id, &project, job).await?;
+
+        let print = CloudPrint {
+            task_id,
+            url: profile.url,
+            model_id: project.model_id,
+            project_id: project.project_id,
+            profile_id: project.profile_id,
+        };
+
+        self.dispatch(device, job, &print).await?;
+
+        Ok(print)
+    }
+
+    /// Get the signed URL of the 3MF file a cloud print profile was created from.
+    async fn get_profile_file(&self, model_id: &str, profile_id: &str) -> Result<ProfileResponse> {
+        let response = self
+            .send(|http| {
+                http.get(
+                    self.endpoints
+                        .api(&format!("v1/iot-service/api/user/profile/{profile_id}")),
+                )
+                .query(&[("model_id", model_id)])
+            })
+            .await?;
+
+        error::json(response).await
+    }
+
+    /// Tell a device to start a print that has been uploaded to the cloud.
+    async fn dispatch(&self, device: &Device, job: &PrintJob, print: &CloudPrint) -> Result<()> {
+        let mut connection = self.connect_mqtt(&[]).await?;
+        connection.subscribe(&device.dev_id).await?;
+
+        // A fresh connection hasn't seen any reports, so ask for one to know whether the printer is busy.
+        connection.request_full_status(&device.dev_id).await?;
+        connection
+            .start_cloud_print(&device.dev_id, job, print)
+            .await?;
+
+        // The print has started by now, so failing to close the connection cleanly shouldn't be reported as failing to print.
+        let _ = connection.disconnect().await;
+
+        Ok(())
+    }
+}
+
+impl Task {
+    /// Print this task again on the given device, downloading the same file from the cloud.
+    ///
+    /// The job starts with the task's plate, bed type and AMS mapping, and is passed through `configure` to change any of
+    /// its settings, e.g. with [`PrintJob::ams_mapping`] when the filaments are loaded in different slots or on a
+    /// different printer. Pass `|job| job` to print it exactly as before.
+    ///
+    /// As with [`Client::submit_print`], the new task is recorded in the account's history before the device is told to
+    /// start it, so it stays there even if the device cannot be reached or rejects the print.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the task can't be printed again, any of the API requests fail, or the
+    /// printer cannot be reached over MQTT or rejects the print.
+    pub async fn reprint(
+        &self,
+        client: &Client,
+        device: &Device,
+        configure: impl FnOnce(PrintJob) -> PrintJob,
+    ) -> Result<CloudPrint> {
+        if !self.is_printable {
+            return Err(Error::NotReprintable);
+        }
+
+        let job = configure(PrintJob::for_cloud_task(self));
+
+        let profile_id = self.profile_id.to_string();
+        let profile = client.get_profile_file(&self.model_id, &profile_id).await?;
+        let task_id = client
+            .create_task_for(&device.dev_id, &self.model_id, &profile_id, &job)
+            .await?;
+
+        let print = CloudPrint {
+            task_id,
+            profile_id,
+            url: profile.url,
+            model_id: self.model_id.clone(),
+            project_id: profile.project_id.unwrap_or_else(|| "0".to_string()),
+        };
+
+        client.dispatch(device, &job, &print).await?;
+
+        Ok(print)
+    }
+}
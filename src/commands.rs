@@ -0,0 +1,600 @@
+use std::time::Instant;
+
+use serde_json::json;
+
+use crate::{
+    gcode, AmsOptions, AmsSlot, CloudPrint, Error, FilamentSetting, GcodeState, Light,
+    LightCommand, Message, MqttConnection, PrintJob, PrinterModel, Report, Result, SpeedProfile,
+};
+
+impl MqttConnection {
+    /// Ask the device to push its full status, and wait for it.
+    ///
+    /// The returned report can be passed to [`PrinterState::apply`](crate::PrinterState::apply) to seed a state before any
+    /// deltas arrive. Requests to the same device are limited to one per [`MqttConnection::set_pushall_interval`], counting
+    /// every request sent, even if the printer didn't answer it.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the last request for this device was too recent, the command cannot be sent, or the printer doesn't respond in time.
+    pub async fn request_full_status(&mut self, device_id: &str) -> Result<Report> {
+        let retry_after = self
+            .last_pushall
+            .get(device_id)
+            .and_then(|last| self.pushall_interval.checked_sub(last.elapsed()))
+            .filter(|retry_after| !retry_after.is_zero());
+
+        if let Some(retry_after) = retry_after {
+            return Err(Error::PushallThrottled { retry_after });
+        }
+
+        // A slow printer is the one most at risk of being throttled, so unanswered requests count too.
+        self.last_pushall
+            .insert(device_id.to_string(), Instant::now());
+
+        let report = self
+            .request(
+                device_id,
+                "pushing",
+                json!({ "command": "pushall", "version": 1, "push_target": 1 }),
+            )
+            .await?;
+
+        if !matches!(report.message, Message::Print(_)) {
+            return Err(Error::CommandRejected(
+                "the printer replied without its status".to_string(),
+            ));
+        }
+
+        Ok(report)
+    }
+
+    /// Start printing a 3MF file that has been uploaded to the device's storage.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the device is already printing, the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn start_print(&mut self, device_id: &str, job: &PrintJob) -> Result<()> {
+        let url = format!("ftp:///{}", job.file.trim_start_matches('/'));
+
+        self.project_file(device_id, job, &url, None).await
+    }
+
+    /// Start a print that has been uploaded to the cloud, having the device download it from there.
+    ///
+    /// [`Client::submit_print`](crate::Client::submit_print) uploads the file and calls this for you.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the device is already printing, the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn start_cloud_print(
+        &mut self,
+        device_id: &str,
+        job: &PrintJob,
+        print: &CloudPrint,
+    ) -> Result<()> {
+        self.project_file(device_id, job, print.url.as_str(), Some(print))
+            .await
+    }
+
+    async fn project_file(
+        &mut self,
+        device_id: &str,
+        job: &PrintJob,
+        url: &str,
+        cloud: Option<&CloudPrint>,
+    ) -> Result<()> {
+        if self.is_busy(device_id) {
+            return Err(Error::PrinterBusy);
+        }
+
+        // Prints started over the LAN aren't tied to a cloud project, which printers expect as zeroes.
+        let (project_id, profile_id, task_id) = cloud.map_or_else(
+            || ("0".to_string(), "0".to_string(), "0".to_string()),
+            |print| {
+                (
+                    print.project_id.clone(),
+                    print.profile_id.clone(),
+                    print.task_id.to_string(),
+                )
+            },
+        );
+
+        self.request(
+            device_id,
+            "print",
+            json!({
+                "command": "project_file",
+                "param": format!("Metadata/plate_{}.gcode", job.plate),
+                "url": url,
+                "subtask_name": job.name,
+                "project_id": project_id,
+                "profile_id": profile_id,
+                "task_id": task_id,
+                "subtask_id": task_id,
+                "md5": "",
+                "bed_type": job.bed_type,
+                "timelapse": job.timelapse,
+                "bed_leveling": job.calibration.bed_leveling,
+                "flow_cali": job.calibration.flow,
+                "vibration_cali": job.calibration.vibration,
+                "layer_inspect": true,
+                "use_ams": job.use_ams,
+                "ams_mapping": job.ams_mapping_ids(),
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Pause the current print on the given device.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn pause(&mut self, device_id: &str) -> Result<()> {
+        self.request(
+            device_id,
+            "print",
+            json!({ "command": "pause", "param": "" }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Resume the paused print on the given device.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn resume(&mut self, device_id: &str) -> Result<()> {
+        self.request(
+            device_id,
+            "print",
+            json!({ "command": "resume", "param": "" }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Stop the current print on the given device. A stopped print cannot be resumed.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn stop(&mut self, device_id: &str) -> Result<()> {
+        self.request(
+            device_id,
+            "print",
+            json!({ "command": "stop", "param": "" }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Switch the print speed of the given device.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn set_speed_profile(
+        &mut self,
+        device_id: &str,
+        profile: SpeedProfile,
+    ) -> Result<()> {
+        self.request(
+            device_id,
+            "print",
+            json!({ "command": "print_speed", "param": (profile as u8).to_string() }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Switch one of the device's lights on, off, or make it flash.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn set_light(
+        &mut self,
+        device_id: &str,
+        light: &Light,
+        command: LightCommand,
+    ) -> Result<()> {
+        let (mode, on_time, off_time, loop_times, interval) = match command {
+            LightCommand::On => ("on", 500, 500, 0, 0),
+            LightCommand::Off => ("off", 500, 500, 0, 0),
+            LightCommand::Flashing {
+                on_time,
+                off_time,
+                loop_times,
+                interval,
+            } => ("flashing", on_time, off_time, loop_times, interval),
+        };
+
+        self.request(
+            device_id,
+            "system",
+            json!({
+                "command": "ledctrl",
+                "led_node": light.node(),
+                "led_mode": mode,
+                "led_on_time": on_time,
+                "led_off_time": off_time,
+                "loop_times": loop_times,
+                "interval_time": interval,
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Run G-code lines on the given device.
+    ///
+    /// To avoid ruining a print, this refuses to run while the device is preparing, printing or paused, and until a
+    /// report from the device has said which of those it is, e.g. after [`MqttConnection::request_full_status`]. Use
+    /// [`MqttConnection::force_gcode`] to skip this check.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the device is printing or its state is unknown, the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn send_gcode(&mut self, device_id: &str, lines: &[&str]) -> Result<()> {
+        if !self.gcode_states.contains_key(device_id) {
+            return Err(Error::UnknownState);
+        }
+        if self.is_busy(device_id) {
+            return Err(Error::PrinterBusy);
+        }
+
+        self.force_gcode(device_id, lines).await
+    }
+
+    /// Run G-code lines on the given device, even if it is currently printing.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn force_gcode(&mut self, device_id: &str, lines: &[&str]) -> Result<()> {
+        let param = lines.iter().fold(String::new(), |mut param, line| {
+            param.push_str(line.trim_end());
+            param.push('\n');
+            param
+        });
+
+        self.request(
+            device_id,
+            "print",
+            json!({ "command": "gcode_line", "param": param }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Home all axes of the given device.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the device is printing or its state is unknown, the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn home(&mut self, device_id: &str) -> Result<()> {
+        self.send_gcode(device_id, &[&gcode::home()]).await
+    }
+
+    /// Move an axis of the given device relative to its current position. See [`gcode::move_axis`].
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the distance is not finite, the device is printing or its state is unknown, the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn move_axis(
+        &mut self,
+        device_id: &str,
+        axis: gcode::Axis,
+        distance: f64,
+        feed_rate: u32,
+    ) -> Result<()> {
+        let lines = gcode::move_axis(axis, distance, feed_rate)?;
+
+        self.send_gcode(
+            device_id,
+            &lines.iter().map(String::as_str).collect::<Vec<_>>(),
+        )
+        .await
+    }
+
+    /// Set the nozzle target temperature of the given device, in degrees Celsius. Use `0` to turn the heater off.
+    ///
+    /// Every model accepts the same G-code for temperatures and fans, so `model` is only used to check its limits.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the temperature is above the model's limit, the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn set_nozzle_temperature(
+        &mut self,
+        device_id: &str,
+        model: &PrinterModel,
+        celsius: u16,
+    ) -> Result<()> {
+        check_range(
+            "nozzle temperature",
+            celsius,
+            model.max_nozzle_temperature(),
+        )?;
+
+        self.force_gcode(device_id, &[&gcode::nozzle_temperature(celsius)])
+            .await
+    }
+
+    /// Set the bed target temperature of the given device, in degrees Celsius. Use `0` to turn the heater off.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the temperature is above the model's limit, the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn set_bed_temperature(
+        &mut self,
+        device_id: &str,
+        model: &PrinterModel,
+        celsius: u16,
+    ) -> Result<()> {
+        check_range("bed temperature", celsius, model.max_bed_temperature())?;
+
+        self.force_gcode(device_id, &[&gcode::bed_temperature(celsius)])
+            .await
+    }
+
+    /// Set the chamber target temperature of the given device, in degrees Celsius. Use `0` to turn the heater off.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the model doesn't have a chamber heater, the temperature is above its limit, the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn set_chamber_temperature(
+        &mut self,
+        device_id: &str,
+        model: &PrinterModel,
+        celsius: u16,
+    ) -> Result<()> {
+        let Some(max) = model.max_chamber_temperature() else {
+            return Err(Error::Unsupported("the chamber heater"));
+        };
+        check_range("chamber temperature", celsius, max)?;
+
+        self.force_gcode(device_id, &[&gcode::chamber_temperature(celsius)])
+            .await
+    }
+
+    /// Set the speed of one of the given device's fans, as a percentage.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the model doesn't have the fan, the speed is above 100%, the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn set_fan_speed(
+        &mut self,
+        device_id: &str,
+        model: &PrinterModel,
+        fan: gcode::Fan,
+        percent: u8,
+    ) -> Result<()> {
+        if !model.has_fan(fan) {
+            return Err(Error::Unsupported(match fan {
+                gcode::Fan::Part => "the part cooling fan",
+                gcode::Fan::Aux => "the auxiliary fan",
+                gcode::Fan::Chamber => "the chamber fan",
+            }));
+        }
+        check_range("fan speed", percent.into(), 100)?;
+
+        self.force_gcode(device_id, &[&gcode::fan_speed(fan, percent)])
+            .await
+    }
+
+    /// Set the filament information for a slot, so the printer knows what's loaded in it.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn set_ams_filament(
+        &mut self,
+        device_id: &str,
+        slot: AmsSlot,
+        filament: &FilamentSetting,
+    ) -> Result<()> {
+        let (ams_id, tray_id) = slot.ids();
+
+        self.request(
+            device_id,
+            "print",
+            json!({
+                "command": "ams_filament_setting",
+                "ams_id": ams_id,
+                "tray_id": tray_id,
+                "tray_type": filament.tray_type,
+                "tray_color": filament.color.to_string(),
+                "tray_info_idx": filament.tray_info_idx,
+                "nozzle_temp_min": filament.nozzle_temp_min,
+                "nozzle_temp_max": filament.nozzle_temp_max,
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Load the filament from a slot, heating the nozzle to the given temperature.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the device is printing, the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn load_filament(
+        &mut self,
+        device_id: &str,
+        slot: AmsSlot,
+        temperature: u16,
+    ) -> Result<()> {
+        self.change_filament(device_id, slot.global_id(), temperature)
+            .await
+    }
+
+    /// Unload the current filament, heating the nozzle to the given temperature.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the device is printing, the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn unload_filament(&mut self, device_id: &str, temperature: u16) -> Result<()> {
+        self.change_filament(device_id, 255, temperature).await
+    }
+
+    async fn change_filament(
+        &mut self,
+        device_id: &str,
+        target: u8,
+        temperature: u16,
+    ) -> Result<()> {
+        if self.is_busy(device_id) {
+            return Err(Error::PrinterBusy);
+        }
+
+        self.request(
+            device_id,
+            "print",
+            json!({
+                "command": "ams_change_filament",
+                "target": target,
+                "curr_temp": temperature,
+                "tar_temp": temperature,
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Configure how the device's AMS detects and tracks filament.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn set_ams_options(&mut self, device_id: &str, options: AmsOptions) -> Result<()> {
+        self.request(
+            device_id,
+            "print",
+            json!({
+                "command": "ams_user_setting",
+                "ams_id": 0,
+                "tray_read_option": options.read_on_insert,
+                "startup_read_option": options.read_on_startup,
+                "calibrate_remain_flag": options.estimate_remaining,
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Enable or disable switching to a spool of the same filament when the current one runs out.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the command cannot be sent, or the printer rejects it or doesn't respond in time.
+    pub async fn set_auto_refill(&mut self, device_id: &str, enabled: bool) -> Result<()> {
+        self.request(
+            device_id,
+            "print",
+            json!({ "command": "print_option", "auto_switch_filament": enabled }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Whether the device was last reported to be preparing a print, printing or paused.
+    fn is_busy(&self, device_id: &str) -> bool {
+        matches!(
+            self.gcode_states.get(device_id),
+            Some(GcodeState::Prepare | GcodeState::Running | GcodeState::Pause)
+        )
+    }
+}
+
+const fn check_range(what: &'static str, value: u16, max: u16) -> Result<()> {
+    if value > max {
+        return Err(Error::OutOfRange { what, value, max });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rumqttc::MqttOptions;
+
+    use super::*;
+
+    fn connection() -> MqttConnection {
+        // Nothing listens on port 1, so every request fails without reaching a printer.
+        MqttConnection::new(MqttOptions::new("test", "127.0.0.1", 1), 10)
+    }
+
+    #[tokio::test]
+    async fn throttles_full_status_requests() {
+        let mut connection = connection();
+        connection
+            .last_pushall
+            .insert("01P00A000000000".to_string(), Instant::now());
+
+        let error = connection
+            .request_full_status("01P00A000000000")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::PushallThrottled { .. }));
+
+        // Other devices have their own limit.
+        let error = connection
+            .request_full_status("01S00A000000000")
+            .await
+            .unwrap_err();
+        assert!(!matches!(error, Error::PushallThrottled { .. }));
+    }
+
+    #[tokio::test]
+    async fn refuses_filament_changes_while_busy() {
+        let mut connection = connection();
+
+        for state in [GcodeState::Prepare, GcodeState::Running, GcodeState::Pause] {
+            connection
+                .gcode_states
+                .insert("01P00A000000000".to_string(), state);
+
+            let error = connection
+                .unload_filament("01P00A000000000", 220)
+                .await
+                .unwrap_err();
+            assert!(matches!(error, Error::PrinterBusy));
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_unanswered_full_status_requests() {
+        let mut connection = connection();
+        connection.set_command_timeout(Duration::from_millis(100));
+
+        let error = connection
+            .request_full_status("01P00A000000000")
+            .await
+            .unwrap_err();
+        assert!(!matches!(error, Error::PushallThrottled { .. }));
+
+        let error = connection
+            .request_full_status("01P00A000000000")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, Error::PushallThrottled { .. }));
+    }
+}
@@ -0,0 +1,155 @@
+use std::time::Duration;
+
+use reqwest::{header::RETRY_AFTER, Response, StatusCode};
+use rumqttc::ConnectReturnCode;
+use serde::de::DeserializeOwned;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to send request")]
+    Http(#[from] reqwest::Error),
+
+    #[error("the API returned {status}: {message}")]
+    Api {
+        status: StatusCode,
+        code: Option<i64>,
+        message: String,
+    },
+
+    #[error("the access token has expired or was rejected")]
+    AuthExpired,
+
+    #[error("rate limited by the API")]
+    RateLimited { retry_after: Option<Duration> },
+
+    #[error("failed to decode response")]
+    Decode {
+        #[source]
+        source: serde_json::Error,
+        body: String,
+    },
+
+    #[error("failed to parse access token")]
+    Token(#[from] jsonwebtoken::errors::Error),
+
+    #[error("the response did not include an access token")]
+    MissingToken,
+
+    #[error("unsupported login type: {0}")]
+    UnsupportedLoginType(String),
+
+    #[error("this login challenge does not accept {0}")]
+    WrongChallenge(&'static str),
+
+    #[error("failed to parse URL")]
+    Url(#[from] url::ParseError),
+
+    #[error("MQTT connection failed")]
+    Mqtt(#[source] Box<rumqttc::ConnectionError>),
+
+    #[error("the MQTT broker rejected the credentials")]
+    MqttAuth,
+
+    #[error("failed to send MQTT request")]
+    MqttRequest(#[from] rumqttc::ClientError),
+
+    #[error("the printer did not respond in time")]
+    Timeout,
+
+    #[error("the printer rejected the command: {0}")]
+    CommandRejected(String),
+
+    #[error("the printer is busy printing")]
+    PrinterBusy,
+
+    #[error("the printer's state is unknown, request its full status first or use force_gcode")]
+    UnknownState,
+
+    #[error("{0} must be a finite number")]
+    NotFinite(&'static str),
+
+    #[error("{what} of {value} is above the printer's limit of {max}")]
+    OutOfRange {
+        what: &'static str,
+        value: u16,
+        max: u16,
+    },
+
+    #[error("the printer does not support {0}")]
+    Unsupported(&'static str),
+
+    #[error("the cloud failed to process the uploaded file: {0}")]
+    UploadFailed(String),
+
+    #[error("the cloud did not finish processing the uploaded file in time")]
+    UploadTimeout,
+
+    #[error("this task cannot be printed again")]
+    NotReprintable,
+
+    #[error("full status was requested too recently, retry in {retry_after:?}")]
+    PushallThrottled { retry_after: Duration },
+
+    #[error("failed to set up TLS")]
+    Tls(#[from] native_tls::Error),
+
+    #[error("FTP request to the printer failed")]
+    Ftp(#[from] suppaftp::FtpError),
+}
+
+impl From<rumqttc::ConnectionError> for Error {
+    fn from(error: rumqttc::ConnectionError) -> Self {
+        match error {
+            rumqttc::ConnectionError::ConnectionRefused(
+                ConnectReturnCode::BadUserNamePassword | ConnectReturnCode::NotAuthorized,
+            ) => Self::MqttAuth,
+            error => Self::Mqtt(Box::new(error)),
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ApiErrorBody {
+    code: Option<i64>,
+    #[serde(alias = "message")]
+    error: Option<String>,
+}
+
+/// Turn a non-successful response into an [`Error`], keeping whatever explanation the API gave in the body.
+pub async fn error_for_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .map(Duration::from_secs);
+
+        return Err(Error::RateLimited { retry_after });
+    }
+
+    let body = response.text().await.unwrap_or_default();
+    let (code, message) = match serde_json::from_str::<ApiErrorBody>(&body) {
+        Ok(ApiErrorBody { code, error }) => (code, error.unwrap_or(body)),
+        Err(_) => (None, body),
+    };
+
+    Err(Error::Api {
+        status,
+        code,
+        message,
+    })
+}
+
+/// Check the status of a response and decode its JSON body.
+pub async fn json<T: DeserializeOwned>(response: Response) -> Result<T> {
+    let body = error_for_status(response).await?.text().await?;
+
+    serde_json::from_str(&body).map_err(|source| Error::Decode { source, body })
+}
@@ -0,0 +1,112 @@
+//! Builders for common G-code lines, to be sent with [`MqttConnection::send_gcode`](crate::MqttConnection::send_gcode).
+
+use crate::{Error, Result};
+
+/// An axis of the printer's motion system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+    /// The extruder.
+    E,
+}
+
+impl Axis {
+    /// Get the letter addressing this axis in G-code.
+    #[must_use]
+    pub const fn letter(self) -> char {
+        match self {
+            Self::X => 'X',
+            Self::Y => 'Y',
+            Self::Z => 'Z',
+            Self::E => 'E',
+        }
+    }
+}
+
+/// A fan controllable with `M106`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fan {
+    /// The part cooling fan on the toolhead.
+    Part = 1,
+    /// The auxiliary fan on the side of the chamber.
+    Aux = 2,
+    /// The chamber exhaust fan.
+    Chamber = 3,
+}
+
+/// Home all axes.
+#[must_use]
+pub fn home() -> String {
+    "G28".to_string()
+}
+
+/// Set the nozzle target temperature, in degrees Celsius, without waiting for it to be reached.
+#[must_use]
+pub fn nozzle_temperature(celsius: u16) -> String {
+    format!("M104 S{celsius}")
+}
+
+/// Set the bed target temperature, in degrees Celsius, without waiting for it to be reached.
+#[must_use]
+pub fn bed_temperature(celsius: u16) -> String {
+    format!("M140 S{celsius}")
+}
+
+/// Set the chamber target temperature, in degrees Celsius, without waiting for it to be reached. Only the X1E has a
+/// chamber heater.
+#[must_use]
+pub fn chamber_temperature(celsius: u16) -> String {
+    format!("M141 S{celsius}")
+}
+
+/// Set the speed of a fan, as a percentage.
+#[must_use]
+pub fn fan_speed(fan: Fan, percent: u8) -> String {
+    let speed = u16::from(percent.min(100)) * 255 / 100;
+
+    format!("M106 P{} S{speed}", fan as u8)
+}
+
+/// Move an axis relative to its current position, in millimeters, at the given feed rate in millimeters per minute.
+///
+/// # Errors
+///
+/// This function can return an [`Error`] if the distance is not a finite number.
+pub fn move_axis(axis: Axis, distance: f64, feed_rate: u32) -> Result<Vec<String>> {
+    if !distance.is_finite() {
+        return Err(Error::NotFinite("distance"));
+    }
+
+    Ok(vec![
+        "G91".to_string(),
+        format!("G1 {}{distance} F{feed_rate}", axis.letter()),
+        "G90".to_string(),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_axis_by_letter() {
+        assert_eq!(
+            move_axis(Axis::Z, -1.5, 600).unwrap(),
+            ["G91", "G1 Z-1.5 F600", "G90"]
+        );
+    }
+
+    #[test]
+    fn rejects_non_finite_distances() {
+        assert!(matches!(
+            move_axis(Axis::X, f64::NAN, 600),
+            Err(Error::NotFinite("distance"))
+        ));
+        assert!(matches!(
+            move_axis(Axis::E, f64::INFINITY, 600),
+            Err(Error::NotFinite("distance"))
+        ));
+    }
+}
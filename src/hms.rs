@@ -0,0 +1,292 @@
+use url::Url;
+
+use crate::{HmsReport, PrintReport};
+
+/// A Health Management System error reported by a printer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HmsError {
+    pub attr: u32,
+    pub code: u32,
+}
+
+/// The part of the printer an [`HmsError`] originated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HmsModule {
+    MotionController,
+    Mainboard,
+    Ams,
+    Toolhead,
+    Camera,
+    Other(u8),
+}
+
+/// How serious an [`HmsError`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HmsSeverity {
+    Fatal,
+    Serious,
+    Common,
+    Info,
+    Unknown(u16),
+}
+
+impl HmsError {
+    /// Get the module the error originated from.
+    #[must_use]
+    pub const fn module(self) -> HmsModule {
+        match (self.attr >> 24) as u8 {
+            0x03 => HmsModule::MotionController,
+            0x05 => HmsModule::Mainboard,
+            0x07 => HmsModule::Ams,
+            0x08 => HmsModule::Toolhead,
+            0x0C => HmsModule::Camera,
+            module => HmsModule::Other(module),
+        }
+    }
+
+    /// Get the severity of the error.
+    #[must_use]
+    pub const fn severity(self) -> HmsSeverity {
+        match (self.code >> 16) as u16 {
+            1 => HmsSeverity::Fatal,
+            2 => HmsSeverity::Serious,
+            3 => HmsSeverity::Common,
+            4 => HmsSeverity::Info,
+            level => HmsSeverity::Unknown(level),
+        }
+    }
+
+    /// Get the canonical code for the error, e.g. `HMS_0300_0100_0001_0001`.
+    #[must_use]
+    pub fn code(self) -> String {
+        format!("HMS_{}", self.short_code())
+    }
+
+    /// Get an English description of the error, if it is a known one.
+    ///
+    /// Only a handful of common errors are described, as Bambu Lab doesn't publish its full list in a usable form. Use
+    /// [`HmsError::wiki_url`] to link to the explanation of any other error.
+    #[must_use]
+    pub fn description(self) -> Option<&'static str> {
+        let key = (u64::from(self.attr) << 32) | u64::from(self.code);
+
+        HMS_DESCRIPTIONS
+            .binary_search_by_key(&key, |(code, _)| *code)
+            .ok()
+            .map(|index| HMS_DESCRIPTIONS[index].1)
+    }
+
+    /// Get the URL of the Bambu Lab wiki page explaining the error.
+    ///
+    /// # Panics
+    ///
+    /// The URL is built from a fixed prefix and hex digits, so this should never panic.
+    #[must_use]
+    pub fn wiki_url(self) -> Url {
+        Url::parse(&format!(
+            "https://wiki.bambulab.com/en/x1/troubleshooting/hmscode/{}",
+            self.short_code()
+        ))
+        .expect("HMS wiki URL should be valid")
+    }
+
+    fn short_code(self) -> String {
+        format!(
+            "{:04X}_{:04X}_{:04X}_{:04X}",
+            self.attr >> 16,
+            self.attr & 0xFFFF,
+            self.code >> 16,
+            self.code & 0xFFFF
+        )
+    }
+}
+
+impl From<HmsReport> for HmsError {
+    fn from(report: HmsReport) -> Self {
+        Self {
+            attr: report.attr,
+            code: report.code,
+        }
+    }
+}
+
+impl std::fmt::Display for HmsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.description() {
+            Some(description) => write!(f, "{}: {description}", self.code()),
+            None => write!(f, "{}", self.code()),
+        }
+    }
+}
+
+impl PrintReport {
+    /// Get the HMS errors the printer is currently reporting.
+    #[must_use]
+    pub fn hms_errors(&self) -> Vec<HmsError> {
+        self.hms
+            .iter()
+            .flatten()
+            .copied()
+            .map(HmsError::from)
+            .collect()
+    }
+
+    /// Get the code of the error that stopped the current print, e.g. `0300_400C`, if there is one.
+    #[must_use]
+    pub fn print_error_code(&self) -> Option<String> {
+        self.print_error
+            .filter(|error| *error != 0)
+            .map(|error| format!("{:04X}_{:04X}", error >> 16, error & 0xFFFF))
+    }
+
+    /// Get an English description of the error that stopped the current print, if there is one and it is a known one.
+    ///
+    /// As with [`HmsError::description`], only common errors are described.
+    #[must_use]
+    pub fn print_error_description(&self) -> Option<&'static str> {
+        let error = self.print_error.filter(|error| *error != 0)?;
+
+        PRINT_ERROR_DESCRIPTIONS
+            .binary_search_by_key(&error, |(code, _)| *code)
+            .ok()
+            .map(|index| PRINT_ERROR_DESCRIPTIONS[index].1)
+    }
+}
+
+/// Descriptions for common HMS errors, keyed by `attr << 32 | code` and sorted for binary search.
+const HMS_DESCRIPTIONS: &[(u64, &str)] = &[
+    (0x0300_0300_0001_0001, "The hotend cooling fan speed is too slow or stopped. It may be stuck or the connector may not be plugged in properly."),
+    (0x0700_2000_0002_0001, "AMS A Slot 1 filament has run out."),
+    (0x0700_2100_0002_0001, "AMS A Slot 2 filament has run out."),
+    (0x0700_2200_0002_0001, "AMS A Slot 3 filament has run out."),
+    (0x0700_2300_0002_0001, "AMS A Slot 4 filament has run out."),
+    (0x0701_2000_0002_0001, "AMS B Slot 1 filament has run out."),
+    (0x0701_2100_0002_0001, "AMS B Slot 2 filament has run out."),
+    (0x0701_2200_0002_0001, "AMS B Slot 3 filament has run out."),
+    (0x0701_2300_0002_0001, "AMS B Slot 4 filament has run out."),
+    (0x0702_2000_0002_0001, "AMS C Slot 1 filament has run out."),
+    (0x0702_2100_0002_0001, "AMS C Slot 2 filament has run out."),
+    (0x0702_2200_0002_0001, "AMS C Slot 3 filament has run out."),
+    (0x0702_2300_0002_0001, "AMS C Slot 4 filament has run out."),
+    (0x0703_2000_0002_0001, "AMS D Slot 1 filament has run out."),
+    (0x0703_2100_0002_0001, "AMS D Slot 2 filament has run out."),
+    (0x0703_2200_0002_0001, "AMS D Slot 3 filament has run out."),
+    (0x0703_2300_0002_0001, "AMS D Slot 4 filament has run out."),
+    (0x0C00_0300_0003_0008, "Spaghetti defects were detected by the AI Print Monitoring. Please check the quality of the printed model before continuing your print."),
+];
+
+/// Descriptions for common errors that stop a print, keyed by `print_error` and sorted for binary search.
+const PRINT_ERROR_DESCRIPTIONS: &[(u32, &str)] = &[
+    (0x0300_400C, "The task was canceled."),
+    (0x0300_8001, "Printing was paused by the user."),
+    (0x0300_8002, "First layer defects were detected by the Micro Lidar. Please check the quality of the printed model before continuing your print."),
+    (0x0300_8003, "Spaghetti defects were detected by the AI Print Monitoring. Please check the quality of the printed model before continuing your print."),
+    (0x0300_8004, "Filament ran out. Please load new filament."),
+    (0x0300_8005, "Toolhead front cover fell off. Please remount the front cover and check to make sure your print is going okay."),
+    (0x0300_8007, "There was an unfinished print job when the printer lost power. If the model is still adhered to the build plate, you can try resuming the print job."),
+    (0x0300_800A, "A filament pile-up was detected by the AI Print Monitoring. Please clean the filament from the waste chute."),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AMS_A_SLOT_2_RUNOUT: HmsError = HmsError {
+        attr: 0x0700_2100,
+        code: 0x0002_0001,
+    };
+
+    #[test]
+    fn descriptions_are_sorted() {
+        assert!(HMS_DESCRIPTIONS
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn print_error_descriptions_are_sorted() {
+        assert!(PRINT_ERROR_DESCRIPTIONS
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn describes_print_errors() {
+        let report = |print_error| PrintReport {
+            print_error: Some(print_error),
+            ..PrintReport::default()
+        };
+
+        assert_eq!(
+            report(0x0300_400C).print_error_code().as_deref(),
+            Some("0300_400C")
+        );
+        assert_eq!(
+            report(0x0300_400C).print_error_description(),
+            Some("The task was canceled.")
+        );
+        assert_eq!(report(0x0300_4FFF).print_error_description(), None);
+        assert_eq!(report(0).print_error_code(), None);
+        assert_eq!(report(0).print_error_description(), None);
+    }
+
+    #[test]
+    fn formats_code() {
+        assert_eq!(AMS_A_SLOT_2_RUNOUT.code(), "HMS_0700_2100_0002_0001");
+    }
+
+    #[test]
+    fn decodes_module_and_severity() {
+        assert_eq!(AMS_A_SLOT_2_RUNOUT.module(), HmsModule::Ams);
+        assert_eq!(AMS_A_SLOT_2_RUNOUT.severity(), HmsSeverity::Serious);
+
+        let fan = HmsError {
+            attr: 0x0300_0300,
+            code: 0x0001_0001,
+        };
+        assert_eq!(fan.module(), HmsModule::MotionController);
+        assert_eq!(fan.severity(), HmsSeverity::Fatal);
+
+        let unknown = HmsError {
+            attr: 0x1200_0000,
+            code: 0x0009_0000,
+        };
+        assert_eq!(unknown.module(), HmsModule::Other(0x12));
+        assert_eq!(unknown.severity(), HmsSeverity::Unknown(9));
+    }
+
+    #[test]
+    fn describes_known_codes() {
+        assert_eq!(
+            AMS_A_SLOT_2_RUNOUT.description(),
+            Some("AMS A Slot 2 filament has run out.")
+        );
+
+        let ams_d_slot_4 = HmsError {
+            attr: 0x0703_2300,
+            code: 0x0002_0001,
+        };
+        assert_eq!(
+            ams_d_slot_4.description(),
+            Some("AMS D Slot 4 filament has run out.")
+        );
+
+        let unknown = HmsError {
+            attr: 0x0700_2000,
+            code: 0x0000_0001,
+        };
+        assert_eq!(unknown.description(), None);
+    }
+
+    #[test]
+    fn every_description_has_a_known_severity() {
+        for (key, _) in HMS_DESCRIPTIONS {
+            let error = HmsError {
+                attr: (key >> 32) as u32,
+                code: (key & 0xFFFF_FFFF) as u32,
+            };
+
+            assert!(!matches!(error.severity(), HmsSeverity::Unknown(_)));
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use chrono::Utc;
+use rumqttc::{MqttOptions, TlsConfiguration, Transport};
+
+use crate::{Device, MqttConnection, PrinterStorage, Result};
+
+/// A printer reachable on the local network, authenticated with its access code instead of the Bambu Lab cloud.
+#[derive(Debug, Clone)]
+pub struct LanPrinter {
+    host: String,
+    serial: String,
+    access_code: String,
+}
+
+impl LanPrinter {
+    /// Create a new printer from its IP address or hostname, serial number and access code.
+    #[must_use]
+    pub fn new(
+        host: impl Into<String>,
+        serial: impl Into<String>,
+        access_code: impl Into<String>,
+    ) -> Self {
+        Self {
+            host: host.into(),
+            serial: serial.into(),
+            access_code: access_code.into(),
+        }
+    }
+
+    /// Create a new printer from a cloud [`Device`] and the address it can be reached at on the local network.
+    #[must_use]
+    pub fn from_device(device: &Device, host: impl Into<String>) -> Self {
+        Self::new(host, &device.dev_id, &device.dev_access_code)
+    }
+
+    /// Get the serial number of the printer.
+    #[must_use]
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+
+    /// Connect to the printer's MQTT broker and subscribe to its reports.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`](crate::Error) if the TLS connector cannot be created or the subscription cannot be queued.
+    pub async fn connect(&self) -> Result<MqttConnection> {
+        // Printers use a self-signed certificate that doesn't match their address.
+        let connector = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true)
+            .build()?;
+
+        // Printers drop the existing connection when another one uses the same client ID.
+        let mut options = MqttOptions::new(
+            format!(
+                "bambulab-rs-{}-{}",
+                self.serial,
+                Utc::now().timestamp_millis()
+            ),
+            &self.host,
+            8883,
+        );
+        options.set_credentials("bblp", &self.access_code);
+        options.set_transport(Transport::tls_with_config(
+            TlsConfiguration::NativeConnector(connector),
+        ));
+
+        let mut connection = MqttConnection::new(options, 16);
+        connection.subscribe(&self.serial).await?;
+
+        Ok(connection)
+    }
+
+    /// Connect to the printer's storage over FTPS, to manage the files on its SD card.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`](crate::Error) if the printer cannot be reached or rejects the access code.
+    pub async fn storage(&self) -> Result<PrinterStorage> {
+        PrinterStorage::connect(&self.host, &self.access_code).await
+    }
+}
@@ -1,137 +1,431 @@
 #![warn(clippy::all, clippy::nursery, clippy::pedantic)]
 
+mod builder;
+mod cloud;
+mod commands;
+mod error;
+pub mod gcode;
+mod hms;
+mod lan;
+mod model;
+mod mqtt;
+mod state;
+mod storage;
 mod types;
 
+use std::sync::{PoisonError, RwLock, RwLockReadGuard};
+
+use futures::{future, stream, Stream, TryStreamExt};
+use reqwest::StatusCode;
 use serde_json::json;
 
-pub use types::{Account, Device, Region, Task};
-use types::{DevicesResponse, LoginResponse, TasksResponse, Token};
+pub use builder::ClientBuilder;
+use builder::Endpoints;
+pub use error::{Error, Result};
+pub use hms::{HmsError, HmsModule, HmsSeverity};
+pub use lan::LanPrinter;
+pub use model::PrinterModel;
+pub use mqtt::MqttConnection;
+pub use state::{PrinterState, StateChange};
+pub use storage::{PrinterStorage, StorageEntry, Timelapse};
+pub use types::{
+    Account, AmsOptions, AmsReport, AmsSlot, AmsTray, AmsTrayReport, AmsUnit, AmsUnitReport,
+    CloudPrint, Color, Credentials, Device, FilamentSetting, GcodeState, HmsReport, Light,
+    LightCommand, LightMode, LightReport, Message, PrintJob, PrintReport, PrintStatus, Project,
+    Region, Report, SpeedProfile, Task, TaskPage, TaskQuery, TaskStatus,
+};
+use types::{DevicesResponse, LoginResponse, Token};
 
 #[derive(Debug)]
 pub struct Client {
     region: Region,
-    client: reqwest::Client,
-    pub(crate) auth_token: Token,
+    http: reqwest::Client,
+    endpoints: Endpoints,
+    auth_token: RwLock<Token>,
 }
 
-#[derive(Debug, thiserror::Error)]
-pub enum LoginError {
-    #[error("failed to send login request")]
-    Reqwest(#[from] reqwest::Error),
+/// The result of a login attempt.
+#[derive(Debug)]
+pub enum Login {
+    /// The credentials were accepted and the client is ready to use.
+    Success(Client),
+    /// The account requires an additional verification step before a token is issued.
+    Challenge(LoginChallenge),
+}
 
-    #[error("failed to parse login response")]
-    Decode(#[from] jsonwebtoken::errors::Error),
+/// The kind of verification the Bambu Lab cloud requested during login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeKind {
+    /// A one-time code must be sent to the account's email and submitted with [`LoginChallenge::submit_code`].
+    VerifyCode,
+    /// A code from the account's authenticator app must be submitted with [`LoginChallenge::submit_tfa`].
+    TwoFactor,
+}
+
+/// A pending login that must be completed with a verification or two-factor code.
+#[derive(Debug)]
+pub struct LoginChallenge {
+    region: Region,
+    email: String,
+    kind: ChallengeKind,
+    http: reqwest::Client,
+    endpoints: Endpoints,
+    tfa_key: Option<String>,
 }
 
 impl Client {
     /// Create a new client by logging in with the provided credentials.
     ///
+    /// Accounts with email verification or two-factor authentication enabled will return a [`Login::Challenge`],
+    /// which must be completed before a [`Client`] is issued.
+    ///
     /// # Errors
     ///
-    /// This function can return a [`LoginError`] if the login request fails or the response cannot be decoded.
-    pub async fn login(region: Region, email: &str, password: &str) -> Result<Self, LoginError> {
-        let client = reqwest::Client::new();
+    /// This function can return an [`Error`] if the login request fails or the response cannot be decoded.
+    pub async fn login(region: Region, email: &str, password: &str) -> Result<Login> {
+        Self::builder(region).login(email, password).await
+    }
 
-        let response = client
-            .post(if region.is_china() {
-                "https://api.bambulab.cn/v1/user-service/user/login"
-            } else {
-                "https://api.bambulab.com/v1/user-service/user/login"
-            })
-            .json(&json!({ "account": email, "password": password }))
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<LoginResponse>()
-            .await?;
+    /// Create a [`ClientBuilder`] to customize the endpoints and HTTP client used by the client.
+    #[must_use]
+    pub const fn builder(region: Region) -> ClientBuilder {
+        ClientBuilder::new(region)
+    }
+
+    /// Create a new client from a previously issued access token.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the token is empty or cannot be decoded.
+    pub fn from_token(region: Region, access_token: impl Into<String>) -> Result<Self> {
+        Self::builder(region).from_token(access_token)
+    }
+
+    /// Create a new client from credentials persisted with [`Client::credentials`].
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the access token is empty or cannot be decoded.
+    pub fn from_credentials(region: Region, credentials: Credentials) -> Result<Self> {
+        Self::builder(region).from_credentials(credentials)
+    }
+
+    pub(crate) fn with_credentials(
+        region: Region,
+        http: reqwest::Client,
+        endpoints: Endpoints,
+        credentials: Credentials,
+    ) -> Result<Self> {
+        if credentials.access_token.is_empty() {
+            return Err(Error::MissingToken);
+        }
 
         Ok(Self {
+            http,
             region,
-            client,
-            auth_token: Token::try_from(response.access_token)?,
+            endpoints,
+            auth_token: RwLock::new(Token::try_from(credentials)?),
         })
     }
 
-    /// Get the account profile for the logged-in user.
+    /// Get the credentials for this client, so they can be persisted and restored with [`Client::from_credentials`].
+    #[must_use]
+    pub fn credentials(&self) -> Credentials {
+        let token = self.token();
+
+        Credentials {
+            access_token: token.jwt.clone(),
+            expires_at: token.expires_at,
+            refresh_token: token.refresh.clone(),
+        }
+    }
+
+    /// Exchange the refresh token for a new access token.
+    ///
+    /// This is done automatically before requests are made with an expiring token, or when the API rejects the current one.
     ///
     /// # Errors
     ///
-    /// This function can return a [`reqwest::Error`] if the request fails.
-    pub async fn get_profile(&self) -> Result<Account, reqwest::Error> {
-        self.client
-            .get(if self.region.is_china() {
-                "https://api.bambulab.cn/v1/user-service/my/profile"
-            } else {
-                "https://api.bambulab.com/v1/user-service/my/profile"
-            })
-            .header("Authorization", format!("Bearer {}", self.auth_token.jwt))
+    /// This function can return an [`Error`] if there is no refresh token, the request fails or the new token cannot be decoded.
+    pub async fn refresh(&self) -> Result<()> {
+        let refresh_token = self.token().refresh.clone().ok_or(Error::MissingToken)?;
+
+        let response = self
+            .http
+            .post(self.endpoints.api("v1/user-service/user/refreshtoken"))
+            .json(&json!({ "refreshToken": refresh_token }))
             .send()
-            .await?
-            .error_for_status()?
-            .json()
-            .await
+            .await?;
+        let response = error::json::<LoginResponse>(response).await?;
+
+        let mut credentials = Credentials::from(response);
+        if credentials.access_token.is_empty() {
+            return Err(Error::MissingToken);
+        }
+        credentials.refresh_token.get_or_insert(refresh_token);
+
+        *self
+            .auth_token
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Token::try_from(credentials)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn token(&self) -> RwLockReadGuard<'_, Token> {
+        self.auth_token
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Send an authenticated request, refreshing the access token first if it is about to expire and retrying once if it is rejected.
+    pub(crate) async fn send(
+        &self,
+        request: impl Fn(&reqwest::Client) -> reqwest::RequestBuilder + Send,
+    ) -> Result<reqwest::Response> {
+        let (expires_soon, can_refresh) = {
+            let token = self.token();
+            (token.expires_soon(), token.refresh.is_some())
+        };
+
+        // A failed refresh is not fatal here, the request will surface the auth error instead.
+        if expires_soon {
+            let _ = self.refresh().await;
+        }
+
+        let jwt = self.token().jwt.clone();
+        let response = request(&self.http)
+            .header("Authorization", format!("Bearer {jwt}"))
+            .send()
+            .await?;
+
+        let response = if response.status() == StatusCode::UNAUTHORIZED
+            && can_refresh
+            && self.refresh().await.is_ok()
+        {
+            let jwt = self.token().jwt.clone();
+
+            request(&self.http)
+                .header("Authorization", format!("Bearer {jwt}"))
+                .send()
+                .await?
+        } else {
+            response
+        };
+
+        // Only requests authenticated with the token can tell us it's no longer valid.
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(Error::AuthExpired);
+        }
+
+        error::error_for_status(response).await
+    }
+
+    /// Get the account profile for the logged-in user.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the request fails or the API rejects it.
+    pub async fn get_profile(&self) -> Result<Account> {
+        let response = self
+            .send(|http| http.get(self.endpoints.api("v1/user-service/my/profile")))
+            .await?;
+
+        error::json(response).await
     }
 
     /// Get a list of devices associated with the account.
     ///
     /// # Errors
     ///
-    /// This function can return a [`reqwest::Error`] if the request fails.
-    pub async fn get_devices(&self) -> Result<Vec<Device>, reqwest::Error> {
+    /// This function can return an [`Error`] if the request fails or the API rejects it.
+    pub async fn get_devices(&self) -> Result<Vec<Device>> {
         let response = self
-            .client
-            .get(if self.region.is_china() {
-                "https://api.bambulab.cn/v1/iot-service/api/user/bind"
-            } else {
-                "https://api.bambulab.com/v1/iot-service/api/user/bind"
-            })
-            .header("Authorization", format!("Bearer {}", self.auth_token.jwt))
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<DevicesResponse>()
+            .send(|http| http.get(self.endpoints.api("v1/iot-service/api/user/bind")))
             .await?;
 
-        Ok(response.devices)
+        Ok(error::json::<DevicesResponse>(response).await?.devices)
     }
 
     /// Get a list of tasks associated with the account.
     ///
+    /// This only returns the 500 most recent tasks, use [`Client::tasks`] to walk the full history.
+    ///
     /// # Errors
     ///
-    /// This function can return a [`reqwest::Error`] if the request fails.
-    pub async fn get_tasks(
-        &self,
-        only_device: Option<String>,
-    ) -> Result<Vec<Task>, reqwest::Error> {
+    /// This function can return an [`Error`] if the request fails or the API rejects it.
+    pub async fn get_tasks(&self, only_device: Option<String>) -> Result<Vec<Task>> {
+        let mut query = TaskQuery::new().limit(500);
+        if let Some(device_id) = only_device {
+            query = query.device(device_id);
+        }
+
+        Ok(self.query_tasks(&query).await?.hits)
+    }
+
+    /// Get a single page of tasks matching the query.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the request fails or the API rejects it.
+    pub async fn query_tasks(&self, query: &TaskQuery) -> Result<TaskPage> {
+        let mut page = self.get_tasks_page(query, query.offset).await?;
+        page.hits.retain(|task| query.matches(task));
+
+        Ok(page)
+    }
+
+    /// Stream every task matching the query, fetching further pages as needed.
+    pub fn tasks(&self, query: TaskQuery) -> impl Stream<Item = Result<Task>> + '_ {
+        let filter = query.clone();
+
+        stream::try_unfold(Some(query.offset), move |offset| {
+            let query = query.clone();
+
+            async move {
+                let Some(offset) = offset else {
+                    return Ok(None);
+                };
+
+                let page = self.get_tasks_page(&query, offset).await?;
+                let next = page.next_offset(offset);
+
+                Ok::<_, Error>(Some((stream::iter(page.hits.into_iter().map(Ok)), next)))
+            }
+        })
+        .try_flatten()
+        .try_filter(move |task| future::ready(filter.matches(task)))
+    }
+
+    async fn get_tasks_page(&self, query: &TaskQuery, offset: usize) -> Result<TaskPage> {
+        let mut params = vec![
+            ("limit", query.limit.to_string()),
+            ("offset", offset.to_string()),
+        ];
+        if let Some(device_id) = &query.device_id {
+            params.push(("deviceId", device_id.clone()));
+        }
+
         let response = self
-            .client
-            .get(if self.region.is_china() {
-                "https://api.bambulab.cn/v1/user-service/my/tasks"
-            } else {
-                "https://api.bambulab.com/v1/user-service/my/tasks"
+            .send(|http| {
+                http.get(self.endpoints.api("v1/user-service/my/tasks"))
+                    .query(&params)
             })
-            .query(&[
-                ("limit", "500".to_string()),
-                ("deviceId", only_device.unwrap_or_default()),
-            ])
-            .header("Authorization", format!("Bearer {}", self.auth_token.jwt))
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<TasksResponse>()
             .await?;
 
-        Ok(response.hits)
+        error::json(response).await
+    }
+
+    /// Get the region this client was created for.
+    #[must_use]
+    pub const fn region(&self) -> Region {
+        self.region
     }
 
     /// Get the MQTT host for the client's region.
     #[must_use]
-    pub const fn mqtt_host(&self) -> &str {
-        if self.region.is_china() {
-            "cn.mqtt.bambulab.com"
-        } else {
-            "us.mqtt.bambulab.com"
+    pub fn mqtt_host(&self) -> &str {
+        &self.endpoints.mqtt_host
+    }
+}
+
+impl LoginChallenge {
+    /// Get the kind of verification this challenge expects.
+    #[must_use]
+    pub const fn kind(&self) -> ChallengeKind {
+        self.kind
+    }
+
+    /// Ask the Bambu Lab cloud to email a verification code to the account.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the request fails or this is not a [`ChallengeKind::VerifyCode`] challenge.
+    pub async fn send_verification_code(&self) -> Result<()> {
+        if self.kind != ChallengeKind::VerifyCode {
+            return Err(Error::WrongChallenge("verification codes"));
         }
+
+        let response = self
+            .http
+            .post(self.endpoints.api("v1/user-service/user/sendemail/code"))
+            .json(&json!({ "email": self.email, "type": "codeLogin" }))
+            .send()
+            .await?;
+
+        error::error_for_status(response).await?;
+
+        Ok(())
+    }
+
+    /// Complete the login with the code emailed by [`LoginChallenge::send_verification_code`].
+    ///
+    /// The challenge can be reused to try again if the code is rejected.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the code is rejected or this is not a [`ChallengeKind::VerifyCode`] challenge.
+    pub async fn submit_code(&self, code: &str) -> Result<Client> {
+        if self.kind != ChallengeKind::VerifyCode {
+            return Err(Error::WrongChallenge("verification codes"));
+        }
+
+        let response = self
+            .http
+            .post(self.endpoints.api("v1/user-service/user/login"))
+            .json(&json!({ "account": self.email, "code": code }))
+            .send()
+            .await?;
+        let response = error::json::<LoginResponse>(response).await?;
+
+        Client::with_credentials(
+            self.region,
+            self.http.clone(),
+            self.endpoints.clone(),
+            response.into(),
+        )
+    }
+
+    /// Complete the login with a code from the account's authenticator app.
+    ///
+    /// The challenge can be reused to try again if the code is rejected.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the code is rejected or this is not a [`ChallengeKind::TwoFactor`] challenge.
+    pub async fn submit_tfa(&self, code: &str) -> Result<Client> {
+        let Some(tfa_key) = self
+            .tfa_key
+            .as_ref()
+            .filter(|_| self.kind == ChallengeKind::TwoFactor)
+        else {
+            return Err(Error::WrongChallenge("two-factor codes"));
+        };
+
+        let response = self
+            .http
+            .post(self.endpoints.web("api/sign-in/tfa"))
+            .json(&json!({ "tfaKey": tfa_key, "tfaCode": code }))
+            .send()
+            .await?;
+        let response = error::error_for_status(response).await?;
+
+        // The TFA endpoint hands the access token back as a cookie instead of in the body.
+        let access_token = response
+            .headers()
+            .get_all(reqwest::header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(|cookie| cookie.strip_prefix("token="))
+            .and_then(|cookie| cookie.split(';').next())
+            .ok_or(Error::MissingToken)?
+            .to_string();
+
+        Client::with_credentials(
+            self.region,
+            self.http.clone(),
+            self.endpoints.clone(),
+            Credentials::from_access_token(access_token),
+        )
     }
 }
@@ -0,0 +1,117 @@
+use crate::{gcode::Fan, Device};
+
+/// A Bambu Lab printer model.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(from = "String")]
+pub enum PrinterModel {
+    X1,
+    X1C,
+    X1E,
+    P1P,
+    P1S,
+    A1,
+    A1Mini,
+    Unknown(String),
+}
+
+impl PrinterModel {
+    /// Identify a model from the internal model code (e.g. `C12`) or the product name (e.g. `P1S`) reported by the API.
+    #[must_use]
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "BL-P002" | "X1" => Self::X1,
+            "BL-P001" | "X1 Carbon" | "X1C" => Self::X1C,
+            "C13" | "X1E" => Self::X1E,
+            "C11" | "P1P" => Self::P1P,
+            "C12" | "P1S" => Self::P1S,
+            "N2S" | "A1" => Self::A1,
+            "N1" | "A1 mini" => Self::A1Mini,
+            _ => Self::Unknown(name.to_string()),
+        }
+    }
+
+    /// Whether the model has a lidar sensor for first layer inspection and flow calibration.
+    #[must_use]
+    pub const fn has_lidar(&self) -> bool {
+        matches!(self, Self::X1 | Self::X1C | Self::X1E)
+    }
+
+    /// Whether the model has a built-in camera watching the print.
+    #[must_use]
+    pub const fn has_camera(&self) -> bool {
+        !matches!(self, Self::Unknown(_))
+    }
+
+    /// Whether the model has an enclosed build chamber.
+    #[must_use]
+    pub const fn is_enclosed(&self) -> bool {
+        matches!(self, Self::X1 | Self::X1C | Self::X1E | Self::P1S)
+    }
+
+    /// Get the build volume of the model as `(x, y, z)`, in millimeters.
+    #[must_use]
+    pub const fn build_volume(&self) -> Option<(u16, u16, u16)> {
+        match self {
+            Self::X1 | Self::X1C | Self::X1E | Self::P1P | Self::P1S | Self::A1 => {
+                Some((256, 256, 256))
+            }
+            Self::A1Mini => Some((180, 180, 180)),
+            Self::Unknown(_) => None,
+        }
+    }
+
+    /// Get the highest nozzle temperature the model supports, in degrees Celsius.
+    #[must_use]
+    pub const fn max_nozzle_temperature(&self) -> u16 {
+        match self {
+            Self::X1E => 320,
+            _ => 300,
+        }
+    }
+
+    /// Get the highest bed temperature the model supports, in degrees Celsius.
+    #[must_use]
+    pub const fn max_bed_temperature(&self) -> u16 {
+        match self {
+            Self::X1E => 120,
+            Self::X1 | Self::X1C => 110,
+            Self::A1Mini => 80,
+            Self::P1P | Self::P1S | Self::A1 | Self::Unknown(_) => 100,
+        }
+    }
+
+    /// Get the highest chamber temperature the model can heat to, in degrees Celsius, if it has a chamber heater.
+    #[must_use]
+    pub const fn max_chamber_temperature(&self) -> Option<u16> {
+        match self {
+            Self::X1E => Some(60),
+            _ => None,
+        }
+    }
+
+    /// Whether the model has the given fan.
+    #[must_use]
+    pub const fn has_fan(&self, fan: Fan) -> bool {
+        match fan {
+            Fan::Part => true,
+            Fan::Aux | Fan::Chamber => matches!(self, Self::X1 | Self::X1C | Self::X1E | Self::P1S),
+        }
+    }
+}
+
+impl From<String> for PrinterModel {
+    fn from(name: String) -> Self {
+        Self::from_name(&name)
+    }
+}
+
+impl Device {
+    /// Get the model of this device, falling back to the product name for model codes this crate doesn't know yet.
+    #[must_use]
+    pub fn model(&self) -> PrinterModel {
+        match &self.dev_model_name {
+            PrinterModel::Unknown(_) => self.dev_product_name.clone(),
+            model => model.clone(),
+        }
+    }
+}
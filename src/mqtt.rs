@@ -0,0 +1,247 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use rumqttc::{
+    AsyncClient, Event, EventLoop, MqttOptions, Packet, QoS, TlsConfiguration, Transport,
+};
+use serde_json::json;
+
+use crate::{types::Report, Client, Device, Error, GcodeState, Message, Result};
+
+/// A connection to an MQTT broker relaying printer reports, created with [`Client::connect_mqtt`].
+///
+/// The connection only makes progress while [`MqttConnection::next_report`] is being polled.
+pub struct MqttConnection {
+    sequence_id: u64,
+    client: AsyncClient,
+    events: EventLoop,
+    timeout: Duration,
+    pending: VecDeque<Report>,
+    pub(crate) pushall_interval: Duration,
+    pub(crate) last_pushall: HashMap<String, Instant>,
+    pub(crate) gcode_states: HashMap<String, GcodeState>,
+}
+
+impl std::fmt::Debug for MqttConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MqttConnection")
+            .field("broker", &self.events.mqtt_options.broker_address())
+            .finish_non_exhaustive()
+    }
+}
+
+impl MqttConnection {
+    /// Create a connection, with room to queue `capacity` requests before the event loop is first polled.
+    pub(crate) fn new(mut options: MqttOptions, capacity: usize) -> Self {
+        // Full status reports are far larger than the default packet size limit.
+        options.set_max_packet_size(1024 * 1024, 64 * 1024);
+        options.set_keep_alive(Duration::from_secs(30));
+
+        let (client, events) = AsyncClient::new(options, capacity);
+
+        Self {
+            client,
+            events,
+            sequence_id: 0,
+            pending: VecDeque::new(),
+            last_pushall: HashMap::new(),
+            gcode_states: HashMap::new(),
+            timeout: Duration::from_secs(10),
+            pushall_interval: Duration::from_secs(300),
+        }
+    }
+
+    /// Set how long to wait for a printer to acknowledge a command. Defaults to 10 seconds.
+    pub const fn set_command_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Set the minimum time between full status requests to the same device. Defaults to 5 minutes.
+    ///
+    /// P1-series printers struggle when asked for their full status too often, so keep this generous for them.
+    pub const fn set_pushall_interval(&mut self, interval: Duration) {
+        self.pushall_interval = interval;
+    }
+
+    /// Start receiving reports from the device with the given serial number.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the connection has been closed.
+    pub async fn subscribe(&mut self, device_id: &str) -> Result<()> {
+        self.client
+            .subscribe(format!("device/{device_id}/report"), QoS::AtMostOnce)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Wait for the next report from any of the subscribed devices.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the connection fails or a report cannot be decoded.
+    /// The connection is re-established on the next call after a failure.
+    pub async fn next_report(&mut self) -> Result<Report> {
+        if let Some(report) = self.pending.pop_front() {
+            return Ok(report);
+        }
+
+        self.poll_report().await
+    }
+
+    /// Send a command to a device and wait for the report acknowledging it.
+    ///
+    /// `command` is sent under the `kind` key (e.g. `print` or `system`) with a fresh sequence ID, and any unrelated
+    /// reports received while waiting are kept for [`MqttConnection::next_report`].
+    pub(crate) async fn request(
+        &mut self,
+        device_id: &str,
+        kind: &str,
+        mut command: serde_json::Value,
+    ) -> Result<Report> {
+        self.sequence_id += 1;
+        let sequence_id = self.sequence_id.to_string();
+        command["sequence_id"] = json!(sequence_id);
+
+        self.client
+            .publish(
+                format!("device/{device_id}/request"),
+                QoS::AtMostOnce,
+                false,
+                json!({ kind: command }).to_string(),
+            )
+            .await?;
+
+        let timeout = self.timeout;
+        tokio::time::timeout(timeout, async {
+            loop {
+                let report = match self.poll_report().await {
+                    Ok(report) => report,
+                    // A malformed report from another device shouldn't abort the request.
+                    Err(Error::Decode { .. }) => continue,
+                    Err(error) => return Err(error),
+                };
+
+                if report.device_id != device_id
+                    || report.message.sequence_id().as_deref() != Some(&sequence_id)
+                {
+                    self.pending.push_back(report);
+                    continue;
+                }
+
+                if let Some(reason) = report.message.rejection() {
+                    return Err(Error::CommandRejected(reason));
+                }
+
+                return Ok(report);
+            }
+        })
+        .await
+        .map_err(|_| Error::Timeout)?
+    }
+
+    async fn poll_report(&mut self) -> Result<Report> {
+        loop {
+            let Event::Incoming(Packet::Publish(publish)) = self.events.poll().await? else {
+                continue;
+            };
+
+            let Some(device_id) = publish
+                .topic
+                .strip_prefix("device/")
+                .and_then(|topic| topic.strip_suffix("/report"))
+            else {
+                continue;
+            };
+
+            let report = Report::parse(device_id, &publish.payload)?;
+            if let Message::Print(print) = &report.message {
+                if let Some(state) = &print.gcode_state {
+                    self.gcode_states
+                        .insert(report.device_id.clone(), state.clone());
+                }
+            }
+
+            return Ok(report);
+        }
+    }
+
+    /// Replace the password used the next time the connection is established, keeping the username.
+    pub(crate) fn set_password(&mut self, password: &str) {
+        if let Some(login) = self.events.mqtt_options.credentials() {
+            self.events
+                .mqtt_options
+                .set_credentials(login.username, password);
+        }
+    }
+
+    /// Disconnect from the broker.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the connection has already been closed.
+    pub async fn disconnect(&mut self) -> Result<()> {
+        self.client.disconnect().await?;
+
+        Ok(())
+    }
+}
+
+impl Client {
+    /// Connect to the Bambu Lab cloud MQTT broker and subscribe to reports from the given devices.
+    ///
+    /// The broker authenticates with the access token the client holds at this point, and reconnects reuse it. Once the
+    /// token expires or is refreshed, reconnecting fails with [`Error::MqttAuth`]; call [`Client::reauthorize_mqtt`] and
+    /// keep polling the connection to reconnect with the current token.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the account profile cannot be fetched or the subscriptions cannot be queued.
+    pub async fn connect_mqtt(&self, devices: &[Device]) -> Result<MqttConnection> {
+        let account = self.get_profile().await?;
+
+        let mut options = MqttOptions::new(
+            format!(
+                "bambulab-rs-{}-{}",
+                account.uid,
+                Utc::now().timestamp_millis()
+            ),
+            self.mqtt_host(),
+            self.endpoints.mqtt_port,
+        );
+        options.set_credentials(format!("u_{}", account.uid), self.token().jwt.clone());
+        if self.endpoints.mqtt_tls {
+            options.set_transport(Transport::tls_with_config(TlsConfiguration::Native));
+        }
+
+        let mut connection = MqttConnection::new(options, devices.len() + 16);
+        for device in devices {
+            connection.subscribe(&device.dev_id).await?;
+        }
+
+        Ok(connection)
+    }
+
+    /// Update a cloud MQTT connection to authenticate with the client's current access token, refreshing it first if it's about to expire.
+    ///
+    /// The new token is used the next time the connection is established, e.g. after [`Error::MqttAuth`].
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`] if the access token needs refreshing and the refresh fails.
+    pub async fn reauthorize_mqtt(&self, connection: &mut MqttConnection) -> Result<()> {
+        let expires_soon = self.token().expires_soon();
+        if expires_soon {
+            self.refresh().await?;
+        }
+
+        let jwt = self.token().jwt.clone();
+        connection.set_password(&jwt);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,434 @@
+use crate::{
+    AmsReport, AmsTrayReport, AmsUnitReport, Device, GcodeState, Light, LightMode, Message,
+    PrintReport, Report,
+};
+
+/// The merged state of a printer, built up from the full and delta reports it sends.
+#[derive(Debug, Clone)]
+pub struct PrinterState {
+    device_id: String,
+    name: Option<String>,
+    report: PrintReport,
+}
+
+/// A notable change detected while applying a report to a [`PrinterState`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateChange {
+    /// The G-code state changed, e.g. from [`GcodeState::Running`] to [`GcodeState::Finish`].
+    GcodeState {
+        from: Option<GcodeState>,
+        to: GcodeState,
+    },
+    /// The print progress changed, as a percentage.
+    Progress(u8),
+    /// The printer moved on to a new layer.
+    Layer(u32),
+    /// The print error code changed, with `0` meaning the error was cleared.
+    PrintError(u32),
+    /// A light was switched to a different mode.
+    Light { light: Light, mode: LightMode },
+}
+
+impl PrinterState {
+    /// Create an empty state for the printer with the given serial number.
+    #[must_use]
+    pub fn new(device_id: impl Into<String>) -> Self {
+        Self {
+            name: None,
+            device_id: device_id.into(),
+            report: PrintReport::default(),
+        }
+    }
+
+    /// Create an empty state for a device bound to the account.
+    #[must_use]
+    pub fn for_device(device: &Device) -> Self {
+        Self {
+            name: Some(device.name.clone()),
+            ..Self::new(&device.dev_id)
+        }
+    }
+
+    /// Get the serial number of the printer.
+    #[must_use]
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    /// Get the name of the printer, if the state was created from a [`Device`].
+    #[must_use]
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Get the current merged view of every field the printer has reported.
+    #[must_use]
+    pub const fn snapshot(&self) -> &PrintReport {
+        &self.report
+    }
+
+    /// Apply a report to the state, returning the notable changes it caused.
+    ///
+    /// Reports from other printers, and messages other than print reports, are ignored.
+    pub fn apply(&mut self, report: &Report) -> Vec<StateChange> {
+        let Message::Print(print) = &report.message else {
+            return vec![];
+        };
+
+        if report.device_id != self.device_id {
+            return vec![];
+        }
+
+        let previous = self.report.clone();
+        self.report.merge(print.as_ref().clone());
+
+        self.diff(&previous)
+    }
+
+    fn diff(&self, previous: &PrintReport) -> Vec<StateChange> {
+        let mut changes = vec![];
+
+        if let Some(to) = &self.report.gcode_state {
+            if previous.gcode_state.as_ref() != Some(to) {
+                changes.push(StateChange::GcodeState {
+                    to: to.clone(),
+                    from: previous.gcode_state.clone(),
+                });
+            }
+        }
+
+        if let Some(percent) = self.report.mc_percent {
+            if previous.mc_percent != Some(percent) {
+                changes.push(StateChange::Progress(percent));
+            }
+        }
+
+        if let Some(layer) = self.report.layer_num {
+            if previous.layer_num != Some(layer) {
+                changes.push(StateChange::Layer(layer));
+            }
+        }
+
+        if let Some(error) = self.report.print_error {
+            if previous.print_error.unwrap_or_default() != error {
+                changes.push(StateChange::PrintError(error));
+            }
+        }
+
+        for report in self.report.lights_report.iter().flatten() {
+            if previous.light(&report.node) != Some(&report.mode) {
+                changes.push(StateChange::Light {
+                    light: report.node.clone(),
+                    mode: report.mode.clone(),
+                });
+            }
+        }
+
+        changes
+    }
+}
+
+/// Overwrite each of the listed fields on `$target` that are set on `$delta`.
+macro_rules! merge_fields {
+    ($target:ident, $delta:ident, $($field:ident),+ $(,)?) => {
+        $(
+            if $delta.$field.is_some() {
+                $target.$field = $delta.$field;
+            }
+        )+
+    };
+}
+
+impl PrintReport {
+    /// Merge a delta report into this one, keeping any fields the delta doesn't include.
+    ///
+    /// The fields describing a reply to a command (`command`, `sequence_id`, `result` and `reason`) only apply to the
+    /// message they arrived in, so they are never merged.
+    pub(crate) fn merge(&mut self, delta: Self) {
+        merge_fields!(
+            self,
+            delta,
+            nozzle_temper,
+            nozzle_target_temper,
+            bed_temper,
+            bed_target_temper,
+            chamber_temper,
+            gcode_state,
+            gcode_file,
+            gcode_start_time,
+            subtask_name,
+            subtask_id,
+            task_id,
+            project_id,
+            profile_id,
+            print_type,
+            mc_percent,
+            mc_remaining_time,
+            mc_print_stage,
+            mc_print_sub_stage,
+            stg_cur,
+            layer_num,
+            total_layer_num,
+            print_error,
+            spd_lvl,
+            spd_mag,
+            cooling_fan_speed,
+            big_fan1_speed,
+            big_fan2_speed,
+            heatbreak_fan_speed,
+            nozzle_diameter,
+            nozzle_type,
+            wifi_signal,
+            sdcard,
+            hms,
+        );
+
+        // Some printers only report the lights that changed, so merge them by node.
+        if let Some(lights) = delta.lights_report {
+            let current = self.lights_report.get_or_insert_with(Vec::new);
+
+            for light in lights {
+                match current
+                    .iter_mut()
+                    .find(|current| current.node == light.node)
+                {
+                    Some(current) => *current = light,
+                    None => current.push(light),
+                }
+            }
+        }
+
+        if let Some(ams) = delta.ams {
+            self.ams.get_or_insert_with(AmsReport::default).merge(ams);
+        }
+
+        if let Some(vt_tray) = delta.vt_tray {
+            self.vt_tray
+                .get_or_insert_with(AmsTrayReport::default)
+                .merge(vt_tray);
+        }
+
+        self.extra.extend(delta.extra);
+    }
+}
+
+impl AmsReport {
+    fn merge(&mut self, delta: Self) {
+        merge_fields!(
+            self,
+            delta,
+            ams_exist_bits,
+            tray_exist_bits,
+            tray_now,
+            tray_pre,
+            tray_tar,
+            insert_flag,
+            power_on_flag,
+        );
+
+        if let Some(units) = delta.ams {
+            let current = self.ams.get_or_insert_with(Vec::new);
+
+            for unit in units {
+                match current.iter_mut().find(|current| current.id == unit.id) {
+                    Some(current) => current.merge(unit),
+                    None => current.push(unit),
+                }
+            }
+        }
+
+        self.extra.extend(delta.extra);
+    }
+}
+
+impl AmsUnitReport {
+    fn merge(&mut self, delta: Self) {
+        merge_fields!(self, delta, id, humidity, temp);
+
+        if let Some(trays) = delta.tray {
+            let current = self.tray.get_or_insert_with(Vec::new);
+
+            for tray in trays {
+                match current.iter_mut().find(|current| current.id == tray.id) {
+                    Some(current) => current.merge(tray),
+                    None => current.push(tray),
+                }
+            }
+        }
+
+        self.extra.extend(delta.extra);
+    }
+}
+
+impl AmsTrayReport {
+    fn merge(&mut self, delta: Self) {
+        // Trays whose spool was removed are reported with only their ID, so none of the old spool's details apply.
+        if delta.is_bare() {
+            *self = delta;
+            return;
+        }
+
+        merge_fields!(
+            self,
+            delta,
+            id,
+            tray_type,
+            tray_sub_brands,
+            tray_color,
+            tray_info_idx,
+            tag_uid,
+            remain,
+            nozzle_temp_min,
+            nozzle_temp_max,
+            tray_weight,
+        );
+
+        self.extra.extend(delta.extra);
+    }
+
+    fn is_bare(&self) -> bool {
+        self.tray_type.is_none()
+            && self.tray_sub_brands.is_none()
+            && self.tray_color.is_none()
+            && self.tray_info_idx.is_none()
+            && self.tag_uid.is_none()
+            && self.remain.is_none()
+            && self.nozzle_temp_min.is_none()
+            && self.nozzle_temp_max.is_none()
+            && self.tray_weight.is_none()
+            && self.extra.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn report(print: &serde_json::Value) -> Report {
+        Report::parse(
+            "01P00A000000000",
+            json!({ "print": print }).to_string().as_bytes(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn reports_gcode_state_changes() {
+        let mut state = PrinterState::new("01P00A000000000");
+        state.apply(&report(&json!({ "gcode_state": "RUNNING" })));
+
+        let changes = state.apply(&report(&json!({ "gcode_state": "FINISH" })));
+
+        assert_eq!(
+            changes,
+            vec![StateChange::GcodeState {
+                from: Some(GcodeState::Running),
+                to: GcodeState::Finish,
+            }]
+        );
+        assert!(state
+            .apply(&report(&json!({ "gcode_state": "FINISH" })))
+            .is_empty());
+    }
+
+    #[test]
+    fn keeps_fields_missing_from_deltas() {
+        let mut state = PrinterState::new("01P00A000000000");
+        state.apply(&report(
+            &json!({ "gcode_state": "RUNNING", "mc_percent": 10 }),
+        ));
+
+        let changes = state.apply(&report(&json!({ "mc_percent": 11 })));
+
+        assert_eq!(changes, vec![StateChange::Progress(11)]);
+        assert_eq!(state.snapshot().gcode_state, Some(GcodeState::Running));
+        assert_eq!(state.snapshot().mc_percent, Some(11));
+    }
+
+    #[test]
+    fn does_not_keep_reply_fields() {
+        let mut state = PrinterState::new("01P00A000000000");
+        state.apply(&report(&json!({
+            "command": "gcode_line",
+            "sequence_id": "7",
+            "result": "fail",
+            "reason": "busy",
+        })));
+
+        let snapshot = state.snapshot();
+        assert_eq!(snapshot.command, None);
+        assert_eq!(snapshot.sequence_id, None);
+        assert_eq!(snapshot.result, None);
+        assert_eq!(snapshot.reason, None);
+    }
+
+    #[test]
+    fn merges_ams_trays_by_id() {
+        let mut state = PrinterState::new("01P00A000000000");
+        state.apply(&report(&json!({
+            "ams": { "ams": [{
+                "id": "0",
+                "humidity": "4",
+                "tray": [
+                    { "id": "0", "tray_type": "PLA", "remain": 80 },
+                    { "id": "1", "tray_type": "PETG", "remain": 50 },
+                ],
+            }] },
+        })));
+        state.apply(&report(&json!({
+            "ams": { "ams": [{ "id": "0", "tray": [{ "id": "1", "remain": 45 }] }] },
+        })));
+
+        let units = state
+            .snapshot()
+            .ams
+            .as_ref()
+            .and_then(|ams| ams.ams.as_ref())
+            .unwrap();
+        assert_eq!(units.len(), 1);
+        assert_eq!(units[0].humidity, Some(4));
+
+        let trays = units[0].tray.as_ref().unwrap();
+        assert_eq!(trays.len(), 2);
+        assert_eq!(trays[0].remain, Some(80));
+        assert_eq!(trays[1].tray_type.as_deref(), Some("PETG"));
+        assert_eq!(trays[1].remain, Some(45));
+    }
+
+    #[test]
+    fn clears_removed_spools() {
+        let mut state = PrinterState::new("01P00A000000000");
+        state.apply(&report(&json!({
+            "ams": { "ams": [{
+                "id": "0",
+                "tray": [{ "id": "0", "tray_type": "PLA", "remain": 80, "tag_uid": "1234" }],
+            }] },
+        })));
+        state.apply(&report(&json!({
+            "ams": { "ams": [{ "id": "0", "tray": [{ "id": "0" }] }] },
+        })));
+
+        let units = state.snapshot().ams_units();
+        assert_eq!(units[0].trays.len(), 1);
+        assert!(units[0].trays[0].is_empty());
+        assert_eq!(units[0].trays[0].remaining, None);
+        assert_eq!(units[0].trays[0].tag_uid, None);
+    }
+
+    #[test]
+    fn ignores_other_devices() {
+        let mut state = PrinterState::new("01P00A000000000");
+        let other = Report::parse(
+            "01P00A000000001",
+            json!({ "print": { "mc_percent": 50 } })
+                .to_string()
+                .as_bytes(),
+        )
+        .unwrap();
+
+        assert!(state.apply(&other).is_empty());
+        assert_eq!(state.snapshot().mc_percent, None);
+    }
+}
@@ -0,0 +1,420 @@
+use std::path::Path;
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime, TimeDelta, Utc};
+use suppaftp::{
+    async_native_tls::TlsConnector,
+    list::File,
+    tokio::{AsyncNativeTlsConnector, AsyncNativeTlsFtpStream},
+    types::FileType,
+    FtpError, Status,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{Result, Task};
+
+/// The size of the chunks files are transferred in, and how often progress is reported.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Where printers store their timelapse recordings.
+const TIMELAPSE_DIR: &str = "/timelapse";
+
+/// How far a recording's start may be from its task's, since the task and the printer's clock are stamped separately.
+const TIMELAPSE_SLACK: TimeDelta = TimeDelta::minutes(5);
+
+/// The SD card or internal storage of a printer, accessed over FTPS. Created with [`LanPrinter::storage`](crate::LanPrinter::storage).
+pub struct PrinterStorage {
+    ftp: AsyncNativeTlsFtpStream,
+}
+
+impl std::fmt::Debug for PrinterStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PrinterStorage").finish_non_exhaustive()
+    }
+}
+
+/// A file or directory on the printer's storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageEntry {
+    pub name: String,
+    pub size: u64,
+    pub is_dir: bool,
+    /// When the file was last modified in the printer's local time, as listed by the printer. Files older than six months
+    /// are only listed with a date.
+    pub modified: NaiveDateTime,
+}
+
+impl From<File> for StorageEntry {
+    fn from(file: File) -> Self {
+        Self {
+            name: file.name().to_string(),
+            size: file.size() as u64,
+            is_dir: file.is_directory(),
+            // The listing has no time zone, so this is the printer's local time even though it's parsed as UTC.
+            modified: DateTime::<Utc>::from(file.modified()).naive_utc(),
+        }
+    }
+}
+
+/// A timelapse recording on the printer's storage, as returned by [`PrinterStorage::list_timelapses`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timelapse {
+    /// The full path of the video, e.g. `/timelapse/video_2024-03-21_14-05-33.mp4`.
+    pub path: String,
+    pub size: u64,
+    /// When the recording started in the printer's local time, taken from the file name.
+    pub started_at: Option<NaiveDateTime>,
+    /// The full path of the thumbnail for the video, if the printer saved one.
+    pub thumbnail: Option<String>,
+}
+
+impl Timelapse {
+    /// Get when the recording started, given the offset of the printer's clock from UTC.
+    #[must_use]
+    pub fn started_at_utc(&self, utc_offset: FixedOffset) -> Option<DateTime<Utc>> {
+        self.started_at?
+            .and_local_timezone(utc_offset)
+            .single()
+            .map(|started_at| started_at.to_utc())
+    }
+
+    /// Check whether this recording was made during the given task, i.e. it started between the task's start and end.
+    ///
+    /// `utc_offset` is the offset of the printer's clock from UTC, as recordings are named in its local time.
+    #[must_use]
+    pub fn matches(&self, task: &Task, utc_offset: FixedOffset) -> bool {
+        self.started_at_utc(utc_offset).is_some_and(|started_at| {
+            started_at >= task.start_time - TIMELAPSE_SLACK && started_at <= task.end_time
+        })
+    }
+
+    /// Find the task this recording was made during, picking the one that started closest to it if several overlap.
+    ///
+    /// `utc_offset` is the offset of the printer's clock from UTC, as recordings are named in its local time.
+    #[must_use]
+    pub fn find_task<'a>(&self, tasks: &'a [Task], utc_offset: FixedOffset) -> Option<&'a Task> {
+        let started_at = self.started_at_utc(utc_offset)?;
+
+        tasks
+            .iter()
+            .filter(|task| self.matches(task, utc_offset))
+            .min_by_key(|task| (started_at - task.start_time).abs())
+    }
+}
+
+impl PrinterStorage {
+    /// Connect to the printer's FTPS server on port 990 and log in with its access code.
+    pub(crate) async fn connect(host: &str, access_code: &str) -> Result<Self> {
+        // Printers use a self-signed certificate that doesn't match their address.
+        let connector = TlsConnector::new()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true);
+
+        let mut ftp = AsyncNativeTlsFtpStream::connect_secure_implicit(
+            (host, 990),
+            AsyncNativeTlsConnector::from(connector),
+            host,
+        )
+        .await?;
+
+        // Printers advertise their own address for passive transfers, which may not be the one we reached them on.
+        ftp.set_passive_nat_workaround(true);
+        ftp.login("bblp", access_code).await?;
+
+        // Implicit FTPS doesn't negotiate data channel protection on its own, and printers refuse plain transfers.
+        ftp.custom_command("PBSZ 0", &[Status::CommandOk]).await?;
+        ftp.custom_command("PROT P", &[Status::CommandOk]).await?;
+        ftp.transfer_type(FileType::Binary).await?;
+
+        Ok(Self { ftp })
+    }
+
+    /// List the files and directories at the given path, e.g. `/` or `/cache`.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`](crate::Error) if the directory doesn't exist or the connection fails.
+    pub async fn list(&mut self, path: &str) -> Result<Vec<StorageEntry>> {
+        let lines = self.ftp.list(Some(path)).await?;
+
+        Ok(lines
+            .iter()
+            .filter_map(|line| line.parse::<File>().ok())
+            .map(StorageEntry::from)
+            .collect())
+    }
+
+    /// Get the size of a file, in bytes.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`](crate::Error) if the file doesn't exist or the connection fails.
+    pub async fn size(&mut self, path: &str) -> Result<u64> {
+        Ok(self.ftp.size(path).await? as u64)
+    }
+
+    /// Upload the contents of `reader` to the given path, overwriting any existing file.
+    ///
+    /// `progress` is called with the total number of bytes sent so far after every chunk. Returns the size of the uploaded file.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`](crate::Error) if the reader fails, the printer refuses the upload or the connection fails.
+    pub async fn upload<R: AsyncRead + Unpin>(
+        &mut self,
+        path: &str,
+        mut reader: R,
+        mut progress: impl FnMut(u64),
+    ) -> Result<u64> {
+        let mut stream = self.ftp.put_with_stream(path).await?;
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut sent = 0;
+
+        loop {
+            let read = reader
+                .read(&mut buf)
+                .await
+                .map_err(FtpError::ConnectionError)?;
+            if read == 0 {
+                break;
+            }
+
+            stream
+                .write_all(&buf[..read])
+                .await
+                .map_err(FtpError::ConnectionError)?;
+
+            sent += read as u64;
+            progress(sent);
+        }
+
+        stream.finish().await?;
+
+        Ok(sent)
+    }
+
+    /// Download the file at the given path into `writer`.
+    ///
+    /// `progress` is called with the total number of bytes received so far after every chunk. Returns the size of the downloaded file.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`](crate::Error) if the file doesn't exist, the writer fails or the connection fails.
+    pub async fn download<W: AsyncWrite + Unpin>(
+        &mut self,
+        path: &str,
+        mut writer: W,
+        mut progress: impl FnMut(u64),
+    ) -> Result<u64> {
+        let mut stream = self.ftp.retr_as_stream(path).await?;
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut received = 0;
+
+        loop {
+            let read = stream
+                .read(&mut buf)
+                .await
+                .map_err(FtpError::ConnectionError)?;
+            if read == 0 {
+                break;
+            }
+
+            writer
+                .write_all(&buf[..read])
+                .await
+                .map_err(FtpError::ConnectionError)?;
+
+            received += read as u64;
+            progress(received);
+        }
+
+        stream.finish().await?;
+        writer.flush().await.map_err(FtpError::ConnectionError)?;
+
+        Ok(received)
+    }
+
+    /// List the timelapse recordings on the printer, with their thumbnails.
+    ///
+    /// Use [`Timelapse::find_task`] to match each recording to the [`Task`] it was made during.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`](crate::Error) if the timelapse directory cannot be listed.
+    pub async fn list_timelapses(&mut self) -> Result<Vec<Timelapse>> {
+        let entries = self.list(TIMELAPSE_DIR).await?;
+
+        // Some printers keep thumbnails in a subdirectory instead of next to the videos.
+        let thumbnail_dir = format!("{TIMELAPSE_DIR}/thumbnail");
+        let mut thumbnails = entries
+            .iter()
+            .filter(|entry| !entry.is_dir && has_extension(&entry.name, &["jpg", "png"]))
+            .map(|entry| format!("{TIMELAPSE_DIR}/{}", entry.name))
+            .collect::<Vec<_>>();
+        if entries
+            .iter()
+            .any(|entry| entry.is_dir && entry.name == "thumbnail")
+        {
+            thumbnails.extend(
+                self.list(&thumbnail_dir)
+                    .await?
+                    .into_iter()
+                    .filter(|entry| !entry.is_dir && has_extension(&entry.name, &["jpg", "png"]))
+                    .map(|entry| format!("{thumbnail_dir}/{}", entry.name)),
+            );
+        }
+
+        Ok(entries
+            .into_iter()
+            .filter(|entry| !entry.is_dir && has_extension(&entry.name, &["mp4", "avi"]))
+            .map(|entry| {
+                let stem = Path::new(&entry.name).file_stem();
+                let thumbnail = thumbnails
+                    .iter()
+                    .find(|path| Path::new(path).file_stem() == stem)
+                    .cloned();
+
+                Timelapse {
+                    thumbnail,
+                    size: entry.size,
+                    started_at: stem.and_then(|stem| parse_recording_time(&stem.to_string_lossy())),
+                    path: format!("{TIMELAPSE_DIR}/{}", entry.name),
+                }
+            })
+            .collect())
+    }
+
+    /// Download a timelapse recording into `writer`. See [`PrinterStorage::download`].
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`](crate::Error) if the recording no longer exists, the writer fails or the connection fails.
+    pub async fn download_timelapse<W: AsyncWrite + Unpin>(
+        &mut self,
+        timelapse: &Timelapse,
+        writer: W,
+        progress: impl FnMut(u64),
+    ) -> Result<u64> {
+        self.download(&timelapse.path, writer, progress).await
+    }
+
+    /// Rename or move a file.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`](crate::Error) if the file doesn't exist or the connection fails.
+    pub async fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        self.ftp.rename(from, to).await?;
+
+        Ok(())
+    }
+
+    /// Delete a file.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`](crate::Error) if the file doesn't exist or the connection fails.
+    pub async fn delete(&mut self, path: &str) -> Result<()> {
+        self.ftp.rm(path).await?;
+
+        Ok(())
+    }
+
+    /// Close the connection to the printer.
+    ///
+    /// # Errors
+    ///
+    /// This function can return an [`Error`](crate::Error) if the connection has already been closed.
+    pub async fn disconnect(mut self) -> Result<()> {
+        self.ftp.quit().await?;
+
+        Ok(())
+    }
+}
+
+fn has_extension(name: &str, extensions: &[&str]) -> bool {
+    Path::new(name).extension().is_some_and(|extension| {
+        extensions
+            .iter()
+            .any(|expected| extension.eq_ignore_ascii_case(expected))
+    })
+}
+
+/// Parse the start time from a recording's file name, e.g. `video_2024-03-21_14-05-33`.
+fn parse_recording_time(stem: &str) -> Option<NaiveDateTime> {
+    let time = stem.strip_prefix("video_")?;
+
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%d_%H-%M-%S").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::types::tests::task;
+
+    #[test]
+    fn parses_recording_time() {
+        assert_eq!(
+            parse_recording_time("video_2024-03-21_14-05-33"),
+            NaiveDate::from_ymd_opt(2024, 3, 21).and_then(|date| date.and_hms_opt(14, 5, 33))
+        );
+        assert_eq!(parse_recording_time("video_latest"), None);
+        assert_eq!(parse_recording_time("model"), None);
+    }
+
+    fn timelapse(stem: &str) -> Timelapse {
+        Timelapse {
+            path: format!("{TIMELAPSE_DIR}/{stem}.mp4"),
+            size: 0,
+            started_at: parse_recording_time(stem),
+            thumbnail: None,
+        }
+    }
+
+    #[test]
+    fn converts_start_time_to_utc() {
+        let timelapse = timelapse("video_2024-03-21_14-05-33");
+        let offset = FixedOffset::east_opt(2 * 3600).unwrap();
+
+        assert_eq!(
+            timelapse.started_at_utc(offset),
+            "2024-03-21T12:05:33Z".parse().ok()
+        );
+    }
+
+    #[test]
+    fn matches_tasks_by_time() {
+        let task = task(1, "2024-03-21T12:00:00Z", "2024-03-21T14:00:00Z");
+        let utc = FixedOffset::east_opt(0).unwrap();
+        let matches = |stem| timelapse(stem).matches(&task, utc);
+
+        // Recordings may start a little before the task, as they're stamped by the printer's clock.
+        assert!(matches("video_2024-03-21_11-56-00"));
+        assert!(matches("video_2024-03-21_14-00-00"));
+        assert!(!matches("video_2024-03-21_11-54-00"));
+        assert!(!matches("video_2024-03-21_14-00-01"));
+        assert!(!matches("video_latest"));
+
+        // Recordings are named in the printer's local time.
+        let recording = timelapse("video_2024-03-21_15-00-00");
+        assert!(!recording.matches(&task, utc));
+        assert!(recording.matches(&task, FixedOffset::east_opt(2 * 3600).unwrap()));
+    }
+
+    #[test]
+    fn finds_the_closest_task() {
+        let tasks = [
+            task(1, "2024-03-21T10:00:00Z", "2024-03-21T14:00:00Z"),
+            task(2, "2024-03-21T12:00:00Z", "2024-03-21T13:00:00Z"),
+            task(3, "2024-03-21T15:00:00Z", "2024-03-21T16:00:00Z"),
+        ];
+        let utc = FixedOffset::east_opt(0).unwrap();
+
+        let find = |stem| timelapse(stem).find_task(&tasks, utc).map(|task| task.id);
+        assert_eq!(find("video_2024-03-21_10-01-00"), Some(1));
+        assert_eq!(find("video_2024-03-21_11-58-00"), Some(2));
+        assert_eq!(find("video_2024-03-21_13-30-00"), Some(1));
+        assert_eq!(find("video_2024-03-21_14-30-00"), None);
+    }
+}
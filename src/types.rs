@@ -6,6 +6,8 @@ use serde::{Deserialize, Serialize};
 use serde_json::json;
 use url::Url;
 
+use crate::{error, PrinterModel, Result};
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Region {
     China,
@@ -19,6 +21,36 @@ impl Region {
     pub(crate) const fn is_china(self) -> bool {
         matches!(self, Self::China)
     }
+
+    /// Get the default base URL of the REST API for this region.
+    #[must_use]
+    pub const fn api_url(self) -> &'static str {
+        if self.is_china() {
+            "https://api.bambulab.cn"
+        } else {
+            "https://api.bambulab.com"
+        }
+    }
+
+    /// Get the default base URL of the website API for this region.
+    #[must_use]
+    pub const fn web_url(self) -> &'static str {
+        if self.is_china() {
+            "https://bambulab.cn"
+        } else {
+            "https://bambulab.com"
+        }
+    }
+
+    /// Get the default MQTT broker hostname for this region.
+    #[must_use]
+    pub const fn mqtt_host(self) -> &'static str {
+        if self.is_china() {
+            "cn.mqtt.bambulab.com"
+        } else {
+            "us.mqtt.bambulab.com"
+        }
+    }
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -26,11 +58,38 @@ pub struct Device {
     pub name: String,
     pub online: bool,
     pub dev_id: String,
-    pub print_status: String,
+    pub print_status: PrintStatus,
     pub nozzle_diameter: f64,
-    pub dev_model_name: String,
+    pub dev_model_name: PrinterModel,
     pub dev_access_code: String,
-    pub dev_product_name: String,
+    pub dev_product_name: PrinterModel,
+}
+
+/// The print status of a [`Device`], as reported by the cloud.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(from = "String")]
+pub enum PrintStatus {
+    Idle,
+    Active,
+    Running,
+    Pause,
+    Success,
+    Failed,
+    Unknown(String),
+}
+
+impl From<String> for PrintStatus {
+    fn from(status: String) -> Self {
+        match status.as_str() {
+            "IDLE" => Self::Idle,
+            "ACTIVE" => Self::Active,
+            "RUNNING" => Self::Running,
+            "PAUSE" => Self::Pause,
+            "SUCCESS" => Self::Success,
+            "FAILED" => Self::Failed,
+            _ => Self::Unknown(status),
+        }
+    }
 }
 
 impl Device {
@@ -38,29 +97,18 @@ impl Device {
     ///
     /// # Errors
     ///
-    /// This function can return a [`reqwest::Error`] if the request fails.
-    pub async fn get_bambu_camera_url(
-        &self,
-        client: &super::Client,
-    ) -> Result<Url, DeviceCameraError> {
+    /// This function can return an [`Error`](crate::Error) if the request fails or the API rejects it.
+    pub async fn get_bambu_camera_url(&self, client: &super::Client) -> Result<Url> {
+        let username = client.token().username.clone();
+
         let response = client
-            .client
-            .post(if client.region.is_china() {
-                "https://api.bambulab.cn/v1/iot-service/api/user/ttcode"
-            } else {
-                "https://api.bambulab.com/v1/iot-service/api/user/ttcode"
+            .send(|http| {
+                http.post(client.endpoints.api("v1/iot-service/api/user/ttcode"))
+                    .header("user-id", &username)
+                    .json(&json!({ "dev_id": self.dev_id }))
             })
-            .header(
-                "Authorization",
-                &format!("Bearer {}", client.auth_token.jwt),
-            )
-            .header("user-id", client.auth_token.username.clone())
-            .json(&json!({ "dev_id": self.dev_id }))
-            .send()
-            .await?
-            .error_for_status()?
-            .json::<DeviceCameraResponse>()
             .await?;
+        let response = error::json::<DeviceCameraResponse>(response).await?;
 
         Ok(Url::from_str(&format!(
             "bambu:///{}?authkey={}&passwd={}&region={}",
@@ -69,6 +117,767 @@ impl Device {
     }
 }
 
+/// A message published by a printer on its `device/{dev_id}/report` topic.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// The serial number of the printer that sent the report.
+    pub device_id: String,
+    pub message: Message,
+}
+
+/// The contents of a [`Report`], keyed by the top-level field of the payload.
+#[derive(Debug, Clone)]
+pub enum Message {
+    Print(Box<PrintReport>),
+    Info(serde_json::Value),
+    System(serde_json::Value),
+    Other(String, serde_json::Value),
+}
+
+impl Message {
+    /// Get the sequence ID of the request this message is replying to, if any.
+    #[must_use]
+    pub fn sequence_id(&self) -> Option<String> {
+        match self {
+            Self::Print(report) => report.sequence_id.clone(),
+            Self::Info(value) | Self::System(value) | Self::Other(_, value) => {
+                match value.get("sequence_id")? {
+                    serde_json::Value::String(id) => Some(id.clone()),
+                    serde_json::Value::Number(id) => Some(id.to_string()),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Get the reason a command was rejected, if this message is a failed reply.
+    pub(crate) fn rejection(&self) -> Option<String> {
+        let (result, reason) = match self {
+            Self::Print(report) => (report.result.as_deref(), report.reason.as_deref()),
+            Self::Info(value) | Self::System(value) | Self::Other(_, value) => (
+                value.get("result").and_then(serde_json::Value::as_str),
+                value.get("reason").and_then(serde_json::Value::as_str),
+            ),
+        };
+
+        result
+            .is_some_and(|result| result.eq_ignore_ascii_case("fail"))
+            .then(|| reason.unwrap_or_default().to_string())
+    }
+}
+
+impl Report {
+    pub(crate) fn parse(device_id: &str, payload: &[u8]) -> Result<Self> {
+        let decode_error = |source| crate::Error::Decode {
+            source,
+            body: String::from_utf8_lossy(payload).into_owned(),
+        };
+
+        let payload = serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(payload)
+            .map_err(decode_error)?;
+
+        let Some((kind, value)) = payload.into_iter().next() else {
+            return Err(decode_error(serde::de::Error::custom("empty report")));
+        };
+
+        Ok(Self {
+            device_id: device_id.to_string(),
+            message: match kind.as_str() {
+                "print" => Message::Print(serde_json::from_value(value).map_err(decode_error)?),
+                "info" => Message::Info(value),
+                "system" => Message::System(value),
+                _ => Message::Other(kind, value),
+            },
+        })
+    }
+}
+
+/// The `print` message of a report, sent as a full `push_status` or as a delta with only the changed fields.
+///
+/// Every field is optional since P1 and A1 printers omit anything that hasn't changed. Fields without a typed
+/// counterpart are kept in [`PrintReport::extra`].
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct PrintReport {
+    pub command: Option<String>,
+    #[serde(deserialize_with = "lenient_string")]
+    pub sequence_id: Option<String>,
+    /// The outcome of a command, on replies to requests.
+    pub result: Option<String>,
+    pub reason: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub nozzle_temper: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub nozzle_target_temper: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub bed_temper: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub bed_target_temper: Option<f64>,
+    #[serde(deserialize_with = "lenient")]
+    pub chamber_temper: Option<f64>,
+
+    pub gcode_state: Option<GcodeState>,
+    pub gcode_file: Option<String>,
+    #[serde(deserialize_with = "lenient")]
+    pub gcode_start_time: Option<i64>,
+    pub subtask_name: Option<String>,
+    #[serde(deserialize_with = "lenient_string")]
+    pub subtask_id: Option<String>,
+    #[serde(deserialize_with = "lenient_string")]
+    pub task_id: Option<String>,
+    #[serde(deserialize_with = "lenient_string")]
+    pub project_id: Option<String>,
+    #[serde(deserialize_with = "lenient_string")]
+    pub profile_id: Option<String>,
+    pub print_type: Option<String>,
+
+    /// Print progress, as a percentage.
+    #[serde(deserialize_with = "lenient")]
+    pub mc_percent: Option<u8>,
+    /// Estimated remaining time, in minutes.
+    #[serde(deserialize_with = "lenient")]
+    pub mc_remaining_time: Option<u32>,
+    #[serde(deserialize_with = "lenient")]
+    pub mc_print_stage: Option<u32>,
+    #[serde(deserialize_with = "lenient")]
+    pub mc_print_sub_stage: Option<u32>,
+    #[serde(deserialize_with = "lenient")]
+    pub stg_cur: Option<i32>,
+    #[serde(deserialize_with = "lenient")]
+    pub layer_num: Option<u32>,
+    #[serde(deserialize_with = "lenient")]
+    pub total_layer_num: Option<u32>,
+    #[serde(deserialize_with = "lenient")]
+    pub print_error: Option<u32>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub spd_lvl: Option<u8>,
+    #[serde(deserialize_with = "lenient")]
+    pub spd_mag: Option<u32>,
+
+    /// Fan speeds, from 0 to 15.
+    #[serde(deserialize_with = "lenient")]
+    pub cooling_fan_speed: Option<u8>,
+    #[serde(deserialize_with = "lenient")]
+    pub big_fan1_speed: Option<u8>,
+    #[serde(deserialize_with = "lenient")]
+    pub big_fan2_speed: Option<u8>,
+    #[serde(deserialize_with = "lenient")]
+    pub heatbreak_fan_speed: Option<u8>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub nozzle_diameter: Option<f64>,
+    pub nozzle_type: Option<String>,
+    pub wifi_signal: Option<String>,
+    pub sdcard: Option<bool>,
+
+    pub lights_report: Option<Vec<LightReport>>,
+    pub hms: Option<Vec<HmsReport>>,
+    pub ams: Option<AmsReport>,
+    pub vt_tray: Option<AmsTrayReport>,
+
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl PrintReport {
+    /// Get the last reported mode of the given light.
+    #[must_use]
+    pub fn light(&self, light: &Light) -> Option<&LightMode> {
+        self.lights_report
+            .as_ref()?
+            .iter()
+            .find(|report| &report.node == light)
+            .map(|report| &report.mode)
+    }
+
+    /// Get the speed profile the printer is currently using.
+    #[must_use]
+    pub fn speed_profile(&self) -> Option<SpeedProfile> {
+        SpeedProfile::try_from(self.spd_lvl?).ok()
+    }
+
+    /// Get the slot the printer is currently feeding filament from.
+    #[must_use]
+    pub fn active_slot(&self) -> Option<AmsSlot> {
+        AmsSlot::from_global_id(self.ams.as_ref()?.tray_now?)
+    }
+
+    /// Get the state of every AMS unit connected to the printer.
+    #[must_use]
+    pub fn ams_units(&self) -> Vec<AmsUnit> {
+        let active = self.active_slot();
+
+        self.ams
+            .iter()
+            .flat_map(|ams| ams.ams.iter().flatten())
+            .filter_map(|unit| {
+                let id = unit.id?;
+
+                Some(AmsUnit {
+                    id,
+                    humidity_level: unit.humidity,
+                    temperature: unit.temp,
+                    trays: unit
+                        .tray
+                        .iter()
+                        .flatten()
+                        .filter_map(|tray| {
+                            let slot = AmsSlot::Ams {
+                                unit: id,
+                                tray: tray.id?,
+                            };
+
+                            Some(AmsTray::new(slot, tray, active == Some(slot)))
+                        })
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+
+    /// Get the state of the external spool holder.
+    #[must_use]
+    pub fn external_spool(&self) -> Option<AmsTray> {
+        let tray = self.vt_tray.as_ref()?;
+
+        Some(AmsTray::new(
+            AmsSlot::External,
+            tray,
+            self.active_slot() == Some(AmsSlot::External),
+        ))
+    }
+}
+
+/// The print speed levels exposed by Bambu Lab printers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedProfile {
+    Silent = 1,
+    Standard = 2,
+    Sport = 3,
+    Ludicrous = 4,
+}
+
+impl TryFrom<u8> for SpeedProfile {
+    type Error = u8;
+
+    fn try_from(level: u8) -> std::result::Result<Self, Self::Error> {
+        match level {
+            1 => Ok(Self::Silent),
+            2 => Ok(Self::Standard),
+            3 => Ok(Self::Sport),
+            4 => Ok(Self::Ludicrous),
+            _ => Err(level),
+        }
+    }
+}
+
+/// The state of the printer's G-code execution.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(from = "String")]
+pub enum GcodeState {
+    Idle,
+    Prepare,
+    Slicing,
+    Running,
+    Pause,
+    Finish,
+    Failed,
+    Unknown(String),
+}
+
+impl From<String> for GcodeState {
+    fn from(state: String) -> Self {
+        match state.as_str() {
+            "IDLE" => Self::Idle,
+            "PREPARE" => Self::Prepare,
+            "SLICING" => Self::Slicing,
+            "RUNNING" => Self::Running,
+            "PAUSE" => Self::Pause,
+            "FINISH" => Self::Finish,
+            "FAILED" => Self::Failed,
+            _ => Self::Unknown(state),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct LightReport {
+    pub node: Light,
+    pub mode: LightMode,
+}
+
+/// A light on the printer that can be controlled with [`MqttConnection::set_light`](crate::MqttConnection::set_light).
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(from = "String")]
+pub enum Light {
+    Chamber,
+    Work,
+    Other(String),
+}
+
+impl Light {
+    pub(crate) fn node(&self) -> &str {
+        match self {
+            Self::Chamber => "chamber_light",
+            Self::Work => "work_light",
+            Self::Other(node) => node,
+        }
+    }
+}
+
+impl From<String> for Light {
+    fn from(node: String) -> Self {
+        match node.as_str() {
+            "chamber_light" => Self::Chamber,
+            "work_light" => Self::Work,
+            _ => Self::Other(node),
+        }
+    }
+}
+
+/// The reported mode of a [`Light`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(from = "String")]
+pub enum LightMode {
+    On,
+    Off,
+    Flashing,
+    Unknown(String),
+}
+
+impl From<String> for LightMode {
+    fn from(mode: String) -> Self {
+        match mode.as_str() {
+            "on" => Self::On,
+            "off" => Self::Off,
+            "flashing" => Self::Flashing,
+            _ => Self::Unknown(mode),
+        }
+    }
+}
+
+/// The mode to switch a [`Light`] to. Times are in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightCommand {
+    On,
+    Off,
+    /// Blink the light `loop_times` times, waiting `interval` between each cycle.
+    Flashing {
+        on_time: u32,
+        off_time: u32,
+        loop_times: u32,
+        interval: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub struct HmsReport {
+    pub attr: u32,
+    pub code: u32,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct AmsReport {
+    pub ams: Option<Vec<AmsUnitReport>>,
+    pub ams_exist_bits: Option<String>,
+    pub tray_exist_bits: Option<String>,
+    #[serde(deserialize_with = "lenient")]
+    pub tray_now: Option<u8>,
+    #[serde(deserialize_with = "lenient")]
+    pub tray_pre: Option<u8>,
+    #[serde(deserialize_with = "lenient")]
+    pub tray_tar: Option<u8>,
+    pub insert_flag: Option<bool>,
+    pub power_on_flag: Option<bool>,
+
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct AmsUnitReport {
+    #[serde(deserialize_with = "lenient")]
+    pub id: Option<u8>,
+    #[serde(deserialize_with = "lenient")]
+    pub humidity: Option<u8>,
+    #[serde(deserialize_with = "lenient")]
+    pub temp: Option<f64>,
+    pub tray: Option<Vec<AmsTrayReport>>,
+
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct AmsTrayReport {
+    #[serde(deserialize_with = "lenient")]
+    pub id: Option<u8>,
+    pub tray_type: Option<String>,
+    pub tray_sub_brands: Option<String>,
+    pub tray_color: Option<String>,
+    pub tray_info_idx: Option<String>,
+    pub tag_uid: Option<String>,
+    #[serde(deserialize_with = "lenient")]
+    pub remain: Option<i32>,
+    #[serde(deserialize_with = "lenient")]
+    pub nozzle_temp_min: Option<u32>,
+    #[serde(deserialize_with = "lenient")]
+    pub nozzle_temp_max: Option<u32>,
+    #[serde(deserialize_with = "lenient")]
+    pub tray_weight: Option<u32>,
+
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A filament slot that can be configured or loaded, either in an AMS unit or the external spool holder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmsSlot {
+    Ams { unit: u8, tray: u8 },
+    External,
+}
+
+impl AmsSlot {
+    /// Get the slot for an index across all AMS units, where `254` is the external spool and `255` means none.
+    #[must_use]
+    pub const fn from_global_id(id: u8) -> Option<Self> {
+        match id {
+            255 => None,
+            254 => Some(Self::External),
+            id => Some(Self::Ams {
+                unit: id / 4,
+                tray: id % 4,
+            }),
+        }
+    }
+
+    /// Get the `(ams_id, tray_id)` pair used to address this slot in commands.
+    pub(crate) const fn ids(self) -> (u8, u8) {
+        match self {
+            Self::Ams { unit, tray } => (unit, tray),
+            Self::External => (255, 254),
+        }
+    }
+
+    /// Get the index of this slot across all AMS units, as used by `ams_change_filament`.
+    pub(crate) const fn global_id(self) -> u8 {
+        match self {
+            Self::Ams { unit, tray } => unit * 4 + tray,
+            Self::External => 254,
+        }
+    }
+}
+
+/// The live state of an AMS unit, as returned by [`PrintReport::ams_units`].
+#[derive(Debug, Clone)]
+pub struct AmsUnit {
+    pub id: u8,
+    /// The humidity index shown in Bambu Studio, from 1 to 5.
+    pub humidity_level: Option<u8>,
+    /// The temperature inside the unit, in degrees Celsius.
+    pub temperature: Option<f64>,
+    pub trays: Vec<AmsTray>,
+}
+
+/// The live state of a filament slot.
+#[derive(Debug, Clone)]
+pub struct AmsTray {
+    pub slot: AmsSlot,
+    /// The filament material, e.g. `PLA`. Empty slots have no type.
+    pub filament_type: Option<String>,
+    pub color: Option<Color>,
+    /// The estimated remaining filament, as a percentage. Only known for Bambu Lab spools.
+    pub remaining: Option<u8>,
+    /// The UID of the spool's RFID tag. Only known for Bambu Lab spools.
+    pub tag_uid: Option<String>,
+    pub tray_info_idx: Option<String>,
+    pub nozzle_temp_min: Option<u32>,
+    pub nozzle_temp_max: Option<u32>,
+    /// Whether the printer is currently feeding filament from this slot.
+    pub active: bool,
+}
+
+impl AmsTray {
+    fn new(slot: AmsSlot, report: &AmsTrayReport, active: bool) -> Self {
+        let non_empty = |value: &Option<String>| value.clone().filter(|value| !value.is_empty());
+
+        Self {
+            slot,
+            active,
+            filament_type: non_empty(&report.tray_type),
+            tray_info_idx: non_empty(&report.tray_info_idx),
+            nozzle_temp_min: report.nozzle_temp_min,
+            nozzle_temp_max: report.nozzle_temp_max,
+            color: report
+                .tray_color
+                .as_deref()
+                .and_then(|color| color.parse().ok()),
+            // Spools without an RFID tag report a remaining percentage of -1.
+            remaining: report
+                .remain
+                .and_then(|remain| u8::try_from(remain).ok())
+                .filter(|remain| *remain <= 100),
+            tag_uid: non_empty(&report.tag_uid).filter(|uid| uid.chars().any(|char| char != '0')),
+        }
+    }
+
+    /// Whether no filament is known to be loaded in this slot.
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.filament_type.is_none()
+    }
+}
+
+/// An RGBA color, as used for filament.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl FromStr for Color {
+    type Err = std::num::ParseIntError;
+
+    /// Parse a color from an `RRGGBB` or `RRGGBBAA` hex string.
+    fn from_str(hex: &str) -> std::result::Result<Self, Self::Err> {
+        let hex = hex.trim_start_matches('#');
+        let channel =
+            |index: usize| u8::from_str_radix(hex.get(index..index + 2).unwrap_or("-"), 16);
+
+        Ok(Self {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+            a: if hex.len() > 6 { channel(6)? } else { 255 },
+        })
+    }
+}
+
+impl std::fmt::Display for Color {
+    /// Format the color as an `RRGGBBAA` hex string, as expected by the printer.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}",
+            self.r, self.g, self.b, self.a
+        )
+    }
+}
+
+/// The filament information for an [`AmsSlot`], as set with [`MqttConnection::set_ams_filament`](crate::MqttConnection::set_ams_filament).
+#[derive(Debug, Clone)]
+pub struct FilamentSetting {
+    /// The filament material, e.g. `PLA`.
+    pub tray_type: String,
+    pub color: Color,
+    /// The Bambu Lab filament preset ID, e.g. `GFA00` for Bambu PLA Basic.
+    pub tray_info_idx: String,
+    pub nozzle_temp_min: u16,
+    pub nozzle_temp_max: u16,
+}
+
+/// Options that control how the AMS detects and tracks filament.
+#[derive(Debug, Clone, Copy)]
+pub struct AmsOptions {
+    /// Read the RFID tag of spools when they are inserted.
+    pub read_on_insert: bool,
+    /// Read the RFID tag of every spool when the printer starts.
+    pub read_on_startup: bool,
+    /// Estimate the remaining filament on Bambu Lab spools.
+    pub estimate_remaining: bool,
+}
+
+/// A print to start from a 3MF file on the printer's storage, with [`MqttConnection::start_print`](crate::MqttConnection::start_print).
+#[derive(Debug, Clone)]
+pub struct PrintJob {
+    pub(crate) file: String,
+    pub(crate) name: String,
+    pub(crate) plate: usize,
+    pub(crate) bed_type: String,
+    pub(crate) use_ams: bool,
+    pub(crate) ams_mapping: Vec<Option<AmsSlot>>,
+    pub(crate) timelapse: bool,
+    pub(crate) calibration: Calibration,
+}
+
+/// The calibrations a [`PrintJob`] runs before printing.
+#[derive(Debug, Clone, Copy)]
+pub struct Calibration {
+    pub bed_leveling: bool,
+    pub flow: bool,
+    pub vibration: bool,
+}
+
+impl PrintJob {
+    /// Create a job printing the first plate of the given file, e.g. `/model.3mf`, without the AMS.
+    #[must_use]
+    pub fn new(file: impl Into<String>) -> Self {
+        let file = file.into();
+        let name = file
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches(".3mf")
+            .to_string();
+
+        Self {
+            file,
+            name,
+            plate: 1,
+            use_ams: false,
+            timelapse: false,
+            ams_mapping: Vec::new(),
+            calibration: Calibration {
+                flow: true,
+                vibration: true,
+                bed_leveling: true,
+            },
+            bed_type: "auto".to_string(),
+        }
+    }
+
+    /// Create a job printing a historical [`Task`] again from a copy of its file on the printer's storage.
+    ///
+    /// The task's plate, bed type and AMS mapping are reused. When printing on a different printer, override the mapping with [`PrintJob::ams_mapping`] to match its AMS.
+    #[must_use]
+    pub fn from_task(task: &Task, file: impl Into<String>) -> Self {
+        Self::new(file).with_task_settings(task)
+    }
+
+    /// Create a job printing a historical [`Task`] again from its file in the cloud, which has no path on the printer.
+    pub(crate) fn for_cloud_task(task: &Task) -> Self {
+        Self::new(String::new()).with_task_settings(task)
+    }
+
+    fn with_task_settings(self, task: &Task) -> Self {
+        let job = self
+            .name(&task.title)
+            .plate(task.plate_index)
+            .ams_mapping(task.ams_slots());
+
+        if task.bed_type.is_empty() {
+            job
+        } else {
+            job.bed_type(&task.bed_type)
+        }
+    }
+
+    /// Set the name the print is shown under on the printer.
+    #[must_use]
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Set which plate of the project to print, starting from 1.
+    #[must_use]
+    pub const fn plate(mut self, plate: usize) -> Self {
+        self.plate = plate;
+        self
+    }
+
+    /// Set the plate the project was sliced for, e.g. `textured_plate`. Defaults to `auto`, which uses the printer's setting.
+    #[must_use]
+    pub fn bed_type(mut self, bed_type: impl Into<String>) -> Self {
+        self.bed_type = bed_type.into();
+        self
+    }
+
+    /// Set which slot feeds each of the project's filaments, in order. Use `None` for filaments the plate doesn't use.
+    ///
+    /// Setting a mapping also enables the AMS.
+    #[must_use]
+    pub fn ams_mapping(mut self, mapping: impl IntoIterator<Item = Option<AmsSlot>>) -> Self {
+        self.ams_mapping = mapping.into_iter().collect();
+        self.use_ams = !self.ams_mapping.is_empty();
+        self
+    }
+
+    /// Enable or disable feeding filament from the AMS instead of the external spool.
+    #[must_use]
+    pub const fn use_ams(mut self, enabled: bool) -> Self {
+        self.use_ams = enabled;
+        self
+    }
+
+    /// Enable or disable recording a timelapse. Disabled by default.
+    #[must_use]
+    pub const fn timelapse(mut self, enabled: bool) -> Self {
+        self.timelapse = enabled;
+        self
+    }
+
+    /// Enable or disable bed leveling before the print. Enabled by default.
+    #[must_use]
+    pub const fn bed_leveling(mut self, enabled: bool) -> Self {
+        self.calibration.bed_leveling = enabled;
+        self
+    }
+
+    /// Enable or disable flow dynamics calibration before the print. Enabled by default.
+    #[must_use]
+    pub const fn flow_calibration(mut self, enabled: bool) -> Self {
+        self.calibration.flow = enabled;
+        self
+    }
+
+    /// Enable or disable vibration compensation calibration before the print. Enabled by default.
+    #[must_use]
+    pub const fn vibration_calibration(mut self, enabled: bool) -> Self {
+        self.calibration.vibration = enabled;
+        self
+    }
+
+    /// Get the global slot ID for each filament, with `-1` for unused ones.
+    pub(crate) fn ams_mapping_ids(&self) -> Vec<i16> {
+        self.ams_mapping
+            .iter()
+            .map(|slot| slot.map_or(-1, |slot| i16::from(slot.global_id())))
+            .collect()
+    }
+}
+
+/// Printers send some numbers as strings and vice versa, so accept either and treat anything unparseable as missing.
+fn lenient<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: FromStr + Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Lenient<T> {
+        Value(T),
+        String(String),
+        Other(serde::de::IgnoredAny),
+    }
+
+    Ok(match Option::<Lenient<T>>::deserialize(deserializer)? {
+        Some(Lenient::Value(value)) => Some(value),
+        Some(Lenient::String(value)) => value.trim().parse().ok(),
+        Some(Lenient::Other(_)) | None => None,
+    })
+}
+
+fn lenient_string<'de, D>(deserializer: D) -> std::result::Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(
+        match Option::<serde_json::Value>::deserialize(deserializer)? {
+            Some(serde_json::Value::String(value)) => Some(value),
+            Some(serde_json::Value::Number(value)) => Some(value.to_string()),
+            _ => None,
+        },
+    )
+}
+
 #[derive(Debug, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Task {
@@ -79,7 +888,7 @@ pub struct Task {
     pub model_id: String,
     pub title: String,
     pub cover: Url,
-    pub status: u64,
+    pub status: TaskStatus,
     pub feedback_status: u64,
     pub start_time: DateTime<Utc>,
     pub end_time: DateTime<Utc>,
@@ -99,6 +908,126 @@ pub struct Task {
     pub bed_type: String,
 }
 
+impl Task {
+    /// Get the slot that fed each of the task's filaments, in the order used by [`PrintJob::ams_mapping`].
+    #[must_use]
+    pub fn ams_slots(&self) -> Vec<Option<AmsSlot>> {
+        self.ams_detail_mapping
+            .iter()
+            .map(|detail| {
+                u8::try_from(detail.position)
+                    .ok()
+                    .and_then(AmsSlot::from_global_id)
+            })
+            .collect()
+    }
+}
+
+/// The outcome of a [`Task`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(from = "u64")]
+pub enum TaskStatus {
+    Created,
+    Success,
+    Failed,
+    Printing,
+    Unknown(u64),
+}
+
+impl From<u64> for TaskStatus {
+    fn from(status: u64) -> Self {
+        match status {
+            1 => Self::Created,
+            2 => Self::Success,
+            3 => Self::Failed,
+            4 => Self::Printing,
+            _ => Self::Unknown(status),
+        }
+    }
+}
+
+/// Filters and pagination for [`Client::query_tasks`](crate::Client::query_tasks) and [`Client::tasks`](crate::Client::tasks).
+///
+/// The device and pagination are handled by the API, while the date range and status are applied to each page after it is fetched.
+#[derive(Debug, Clone)]
+pub struct TaskQuery {
+    pub(crate) limit: usize,
+    pub(crate) offset: usize,
+    pub(crate) status: Option<TaskStatus>,
+    pub(crate) device_id: Option<String>,
+    pub(crate) after: Option<DateTime<Utc>>,
+    pub(crate) before: Option<DateTime<Utc>>,
+}
+
+impl Default for TaskQuery {
+    fn default() -> Self {
+        Self {
+            limit: 100,
+            offset: 0,
+            after: None,
+            status: None,
+            before: None,
+            device_id: None,
+        }
+    }
+}
+
+impl TaskQuery {
+    /// Create a query for all tasks on the account.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only include tasks printed on the given device.
+    #[must_use]
+    pub fn device(mut self, device_id: impl Into<String>) -> Self {
+        self.device_id = Some(device_id.into());
+        self
+    }
+
+    /// Skip the given number of tasks.
+    #[must_use]
+    pub const fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Set how many tasks are requested per page.
+    #[must_use]
+    pub const fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Only include tasks started at or after the given time.
+    #[must_use]
+    pub const fn after(mut self, after: DateTime<Utc>) -> Self {
+        self.after = Some(after);
+        self
+    }
+
+    /// Only include tasks started before the given time.
+    #[must_use]
+    pub const fn before(mut self, before: DateTime<Utc>) -> Self {
+        self.before = Some(before);
+        self
+    }
+
+    /// Only include tasks with the given status.
+    #[must_use]
+    pub const fn status(mut self, status: TaskStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub(crate) fn matches(&self, task: &Task) -> bool {
+        self.status.is_none_or(|status| task.status == status)
+            && self.after.is_none_or(|after| task.start_time >= after)
+            && self.before.is_none_or(|before| task.start_time < before)
+    }
+}
+
 #[derive(Debug, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AMSDetail {
@@ -143,24 +1072,61 @@ pub struct Personal {
     pub background_url: Url,
 }
 
+/// Credentials that can be persisted and later used to restore a [`Client`](crate::Client) without a password.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Credentials {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl Credentials {
+    /// Create credentials from a bare access token, with no refresh token or known expiry.
+    #[must_use]
+    pub fn from_access_token(access_token: impl Into<String>) -> Self {
+        Self {
+            expires_at: None,
+            refresh_token: None,
+            access_token: access_token.into(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Token {
     pub username: String,
     pub(crate) jwt: String,
+    pub(crate) refresh: Option<String>,
+    pub(crate) expires_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize)]
 struct JWTData {
     username: String,
+    exp: Option<i64>,
+}
+
+impl Token {
+    /// Whether the token can be refreshed and expires within the next minute.
+    pub(crate) fn expires_soon(&self) -> bool {
+        self.refresh.is_some()
+            && self
+                .expires_at
+                .is_some_and(|expires_at| expires_at - Utc::now() < chrono::Duration::minutes(1))
+    }
 }
 
-impl TryFrom<String> for Token {
+impl TryFrom<Credentials> for Token {
     type Error = jsonwebtoken::errors::Error;
 
-    fn try_from(jwt: String) -> Result<Self, Self::Error> {
+    fn try_from(credentials: Credentials) -> Result<Self, Self::Error> {
+        let jwt = credentials.access_token;
+
         let mut validation = Validation::new(Algorithm::RS256);
         validation.insecure_disable_signature_validation();
         validation.validate_aud = false;
+        validation.validate_exp = false;
+        validation.required_spec_claims.clear();
 
         let token: TokenData<JWTData> =
             jsonwebtoken::decode(&jwt, &DecodingKey::from_secret(&[]), &validation)?;
@@ -168,14 +1134,35 @@ impl TryFrom<String> for Token {
         Ok(Self {
             jwt,
             username: token.claims.username,
+            refresh: credentials.refresh_token,
+            expires_at: credentials
+                .expires_at
+                .or_else(|| DateTime::from_timestamp(token.claims.exp?, 0)),
         })
     }
 }
 
 #[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct LoginResponse {
-    #[serde(rename = "accessToken")]
+    #[serde(default)]
     pub(crate) access_token: String,
+    pub(crate) refresh_token: Option<String>,
+    #[serde(default)]
+    pub(crate) expires_in: i64,
+    pub(crate) login_type: Option<String>,
+    pub(crate) tfa_key: Option<String>,
+}
+
+impl From<LoginResponse> for Credentials {
+    fn from(response: LoginResponse) -> Self {
+        Self {
+            access_token: response.access_token,
+            refresh_token: response.refresh_token.filter(|token| !token.is_empty()),
+            expires_at: (response.expires_in > 0)
+                .then(|| Utc::now() + chrono::Duration::seconds(response.expires_in)),
+        }
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -183,12 +1170,67 @@ pub struct DevicesResponse {
     pub devices: Vec<Device>,
 }
 
+/// A single page of task history.
 #[derive(Debug, serde::Deserialize)]
-pub struct TasksResponse {
+pub struct TaskPage {
+    /// The total number of tasks matching the device filter, across all pages.
     pub total: usize,
     pub hits: Vec<Task>,
 }
 
+impl TaskPage {
+    /// Get the offset of the page after this one, fetched from `offset`, or `None` if this is the last page.
+    pub(crate) fn next_offset(&self, offset: usize) -> Option<usize> {
+        let next = offset + self.hits.len();
+
+        // An empty page means the total was stale, so stop instead of requesting the same offset forever.
+        (!self.hits.is_empty() && next < self.total).then_some(next)
+    }
+}
+
+/// A cloud project holding an uploaded 3MF file, created with [`Client::create_project`](crate::Client::create_project).
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Project {
+    pub project_id: String,
+    pub model_id: String,
+    pub profile_id: String,
+    /// The signed storage URL the 3MF file is uploaded to. It only accepts uploads.
+    pub upload_url: Url,
+    /// The ticket used to confirm the upload has been processed.
+    pub upload_ticket: String,
+}
+
+/// A print dispatched to a device through the cloud, with [`Client::submit_print`](crate::Client::submit_print).
+#[derive(Debug, Clone)]
+pub struct CloudPrint {
+    /// The ID of the [`Task`] recording the print in the account's history.
+    pub task_id: u64,
+    pub project_id: String,
+    pub model_id: String,
+    pub profile_id: String,
+    /// The signed URL the printer downloads the 3MF file from.
+    pub url: Url,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct CreateTaskResponse {
+    pub id: u64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct UploadNotification {
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ProfileResponse {
+    pub url: Url,
+    pub project_id: Option<String>,
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct DeviceCameraResponse {
     ttcode: String,
@@ -197,11 +1239,237 @@ struct DeviceCameraResponse {
     region: String,
 }
 
-#[derive(Debug, thiserror::Error)]
-pub enum DeviceCameraError {
-    #[error("failed to get camera URL")]
-    Reqwest(#[from] reqwest::Error),
+#[cfg(test)]
+pub mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Build a task that ran between the given RFC 3339 times, with placeholder values for everything else.
+    pub fn task(id: u64, start_time: &str, end_time: &str) -> Task {
+        serde_json::from_value(json!({
+            "id": id,
+            "designId": 0,
+            "designTitle": "",
+            "instanceId": 0,
+            "modelId": "US000000000000",
+            "title": "Benchy",
+            "cover": "https://example.com/cover.png",
+            "status": 2,
+            "feedbackStatus": 0,
+            "startTime": start_time,
+            "endTime": end_time,
+            "weight": 12.5,
+            "length": 400,
+            "costTime": 3600,
+            "profileId": 1,
+            "plateIndex": 1,
+            "plateName": "",
+            "deviceId": "01P00A000000000",
+            "amsDetailMapping": [],
+            "mode": "cloud_file",
+            "isPublicProfile": false,
+            "isPrintable": true,
+            "deviceModel": "P1S",
+            "deviceName": "P1S",
+            "bedType": "textured_plate",
+        }))
+        .unwrap()
+    }
+
+    fn print_report(value: &serde_json::Value) -> PrintReport {
+        serde_json::from_value(value.clone()).unwrap()
+    }
 
-    #[error("failed to parse camera URL")]
-    Url(#[from] url::ParseError),
+    #[test]
+    fn coerces_numeric_strings() {
+        let report = print_report(&json!({
+            "nozzle_temper": "215.5",
+            "mc_percent": " 42 ",
+            "layer_num": 7,
+        }));
+
+        assert_eq!(report.nozzle_temper, Some(215.5));
+        assert_eq!(report.mc_percent, Some(42));
+        assert_eq!(report.layer_num, Some(7));
+    }
+
+    #[test]
+    fn treats_unparseable_numbers_as_missing() {
+        let report = print_report(&json!({
+            "nozzle_temper": "hot",
+            "mc_percent": [1, 2],
+            "layer_num": null,
+        }));
+
+        assert_eq!(report.nozzle_temper, None);
+        assert_eq!(report.mc_percent, None);
+        assert_eq!(report.layer_num, None);
+    }
+
+    #[test]
+    fn coerces_numbers_to_strings() {
+        assert_eq!(
+            print_report(&json!({ "sequence_id": 12 }))
+                .sequence_id
+                .as_deref(),
+            Some("12")
+        );
+        assert_eq!(
+            print_report(&json!({ "sequence_id": "12" }))
+                .sequence_id
+                .as_deref(),
+            Some("12")
+        );
+        assert_eq!(
+            print_report(&json!({ "sequence_id": true })).sequence_id,
+            None
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_fields() {
+        let report = print_report(&json!({ "mc_percent": 5, "new_field": "value" }));
+
+        assert_eq!(report.extra.get("new_field"), Some(&json!("value")));
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(
+            "FF8000".parse(),
+            Ok(Color {
+                r: 255,
+                g: 128,
+                b: 0,
+                a: 255
+            })
+        );
+        assert_eq!(
+            "#00FF0080".parse(),
+            Ok(Color {
+                r: 0,
+                g: 255,
+                b: 0,
+                a: 128
+            })
+        );
+        assert!("FF80".parse::<Color>().is_err());
+        assert!("FF8000F".parse::<Color>().is_err());
+        assert!("GG8000".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn formats_colors() {
+        let color = Color {
+            r: 255,
+            g: 128,
+            b: 0,
+            a: 255,
+        };
+
+        assert_eq!(color.to_string(), "FF8000FF");
+        assert_eq!(color.to_string().parse(), Ok(color));
+    }
+
+    #[test]
+    fn converts_ams_slots() {
+        assert_eq!(
+            AmsSlot::from_global_id(6),
+            Some(AmsSlot::Ams { unit: 1, tray: 2 })
+        );
+        assert_eq!(AmsSlot::from_global_id(254), Some(AmsSlot::External));
+        assert_eq!(AmsSlot::from_global_id(255), None);
+
+        assert_eq!(AmsSlot::Ams { unit: 1, tray: 2 }.global_id(), 6);
+        assert_eq!(AmsSlot::External.global_id(), 254);
+
+        assert_eq!(AmsSlot::Ams { unit: 1, tray: 2 }.ids(), (1, 2));
+        assert_eq!(AmsSlot::External.ids(), (255, 254));
+    }
+
+    #[test]
+    fn reports_ams_units() {
+        let report = print_report(&json!({
+            "ams": {
+                "tray_now": "1",
+                "ams": [{
+                    "id": "0",
+                    "humidity": "4",
+                    "tray": [
+                        { "id": "0", "tray_type": "PLA", "tray_color": "FF8000FF", "remain": 80, "tag_uid": "0000000000000000" },
+                        { "id": "1", "tray_type": "PETG", "remain": -1 },
+                        { "id": "2" },
+                    ],
+                }],
+            },
+            "vt_tray": { "id": "254", "tray_type": "TPU" },
+        }));
+
+        let units = report.ams_units();
+        assert_eq!(units.len(), 1);
+        assert_eq!(units[0].humidity_level, Some(4));
+
+        let trays = &units[0].trays;
+        assert_eq!(trays.len(), 3);
+        assert_eq!(trays[0].slot, AmsSlot::Ams { unit: 0, tray: 0 });
+        assert_eq!(trays[0].remaining, Some(80));
+        assert_eq!(trays[0].tag_uid, None);
+        assert_eq!(trays[0].color.map(|color| color.r), Some(255));
+        assert_eq!(trays[1].filament_type.as_deref(), Some("PETG"));
+        assert_eq!(trays[1].remaining, None);
+        assert!(trays[2].is_empty());
+
+        assert_eq!(
+            trays.iter().map(|tray| tray.active).collect::<Vec<_>>(),
+            vec![false, true, false]
+        );
+        assert_eq!(
+            report.active_slot(),
+            Some(AmsSlot::Ams { unit: 0, tray: 1 })
+        );
+
+        let external = report.external_spool().unwrap();
+        assert_eq!(external.slot, AmsSlot::External);
+        assert!(!external.active);
+    }
+
+    #[test]
+    fn filters_tasks_by_time_and_status() {
+        let task = task(1, "2024-03-21T12:00:00Z", "2024-03-21T14:00:00Z");
+        let time = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+
+        assert!(TaskQuery::new().matches(&task));
+        assert!(TaskQuery::new().status(TaskStatus::Success).matches(&task));
+        assert!(!TaskQuery::new().status(TaskStatus::Failed).matches(&task));
+
+        // The range includes its start and excludes its end.
+        assert!(TaskQuery::new()
+            .after(time("2024-03-21T12:00:00Z"))
+            .matches(&task));
+        assert!(!TaskQuery::new()
+            .after(time("2024-03-21T12:00:01Z"))
+            .matches(&task));
+        assert!(TaskQuery::new()
+            .before(time("2024-03-21T12:00:01Z"))
+            .matches(&task));
+        assert!(!TaskQuery::new()
+            .before(time("2024-03-21T12:00:00Z"))
+            .matches(&task));
+    }
+
+    #[test]
+    fn stops_paginating_at_the_last_page() {
+        let page = |hits: u64, total| TaskPage {
+            total,
+            hits: (0..hits)
+                .map(|id| task(id, "2024-03-21T12:00:00Z", "2024-03-21T14:00:00Z"))
+                .collect(),
+        };
+
+        assert_eq!(page(2, 5).next_offset(0), Some(2));
+        assert_eq!(page(2, 5).next_offset(2), Some(4));
+        assert_eq!(page(1, 5).next_offset(4), None);
+        assert_eq!(page(0, 5).next_offset(2), None);
+    }
 }